@@ -1,14 +1,33 @@
 use clap::ArgAction;
+use std::path::Path;
 
 use crate::build_kind::BuildKind;
+use crate::dependency::WildcardPolicy;
+use crate::edit::EditCommand;
+use crate::output_format::OutputFormat;
 
 #[derive(Debug)]
 pub struct Args {
     pub script: Option<String>,
     pub script_args: Vec<String>,
+    pub stdin: bool,
+    pub sha256: Option<String>,
+    pub block: Option<String>,
+    pub repl: bool,
     pub expr: bool,
     pub loop_: bool,
     pub count: bool,
+    pub begin: Option<String>,
+    pub end: Option<String>,
+    pub split: Option<String>,
+    pub regex: Option<String>,
+    pub parse: Option<String>,
+    pub bytes: bool,
+    pub lossy: bool,
+    pub null: bool,
+    pub parallel: Option<usize>,
+    pub format: OutputFormat,
+    pub type_: bool,
     pub base_path: Option<String>,
     pub pkg_path: Option<String>,
     pub gen_pkg_only: bool,
@@ -18,6 +37,13 @@ pub struct Args {
     pub dep: Vec<String>,
     pub extern_: Vec<String>,
     pub force: bool,
+    pub locked: bool,
+    pub frozen: bool,
+    pub offline: bool,
+    pub fetch: bool,
+    pub eject: Option<String>,
+    pub pack: Option<String>,
+    pub pack_output: Option<String>,
     pub unstable_features: Vec<String>,
     pub build_kind: BuildKind,
     pub toolchain_version: Option<String>,
@@ -26,6 +52,16 @@ pub struct Args {
     #[cfg(windows)]
     pub uninstall_file_association: bool,
     pub wrapper: Option<String>,
+    pub wildcard_deps: Option<WildcardPolicy>,
+    pub edit: Option<EditArgs>,
+}
+
+/// The arguments of the `add`, `remove` and `upgrade` subcommands.
+#[derive(Debug)]
+pub struct EditArgs {
+    pub command: EditCommand,
+    pub script: String,
+    pub deps: Vec<String>,
 }
 
 impl Args {
@@ -34,16 +70,55 @@ impl Args {
         let version = option_env!("CARGO_PKG_VERSION").unwrap_or("unknown");
         let about = r#"Compiles and runs a Rust script"#;
 
+        // A script named after one of the commands which edit scripts, like `add.rs`, is still run, as it was before there were any.
+        let is_script = std::env::args_os().nth(1).is_some_and(|arg| {
+            ["add", "remove", "upgrade"].iter().any(|name| arg == *name)
+                && crate::find_script(Path::new(&arg)).is_some()
+        });
+        let edit_commands = if is_script {
+            Vec::new()
+        } else {
+            vec![
+                Command::new("add")
+                    .about("Add dependencies to the embedded manifest of a script")
+                    .arg(Arg::new("script").help("Script file to edit").required(true))
+                    .arg(Arg::new("deps")
+                        .help("Dependencies to add, in the same form as for `--dep`; one without a version gets the latest in the local registry index")
+                        .required(true)
+                        .num_args(1..)
+                    ),
+                Command::new("remove")
+                    .about("Remove dependencies from the embedded manifest of a script")
+                    .arg(Arg::new("script").help("Script file to edit").required(true))
+                    .arg(Arg::new("deps")
+                        .help("Names of the dependencies to remove")
+                        .required(true)
+                        .num_args(1..)
+                    ),
+                Command::new("upgrade")
+                    .about("Raise the version requirements of the dependencies, dev-dependencies and build-dependencies of a script to the latest versions in the local registry index")
+                    .arg(Arg::new("script").help("Script file to edit").required(true))
+                    .arg(Arg::new("deps")
+                        .help("Names of the dependencies to upgrade, instead of all of them")
+                        .num_args(1..)
+                    ),
+            ]
+        };
+
         let app = Command::new(crate::consts::PROGRAM_NAME)
             .version(version)
             .about(about)
+            .args_conflicts_with_subcommands(true)
+            .disable_help_subcommand(true)
+            .subcommand_negates_reqs(true)
+            .subcommands(edit_commands)
             .arg(Arg::new("script")
                 .index(1)
-                .help("Script file or expression to execute")
+                .help("Script file, `file://` or `http(s)://` URL, or expression to execute, or `-` to read the script from stdin")
                 .required_unless_present_any(if cfg!(windows) {
-                    ["clear-cache", "install-file-association", "uninstall-file-association"].iter()
+                    ["clear-cache", "pack", "stdin", "repl", "install-file-association", "uninstall-file-association"].iter()
                 } else {
-                    ["clear-cache"].iter()
+                    ["clear-cache", "pack", "stdin", "repl"].iter()
                 })
                 .conflicts_with_all(if cfg!(windows) {
                     ["install-file-association", "uninstall-file-association"].iter()
@@ -67,9 +142,41 @@ impl Args {
                 .action(ArgAction::SetTrue)
                 .requires("script")
             )
+            .arg(Arg::new("stdin")
+                .help("Read the script from stdin, up to the end or a line holding just `__END__`. All of <script> are arguments to the script")
+                .long("stdin")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["expr_or_loop", "pack"])
+            )
+            .arg(Arg::new("sha256")
+                .help("Only run the script at the URL given as <script> if its SHA-256 digest is DIGEST")
+                .long("sha256")
+                .value_name("DIGEST")
+                .num_args(1)
+                .requires("script")
+                .conflicts_with_all(["expr_or_loop", "stdin", "pack"])
+            )
+            .arg(Arg::new("block")
+                .help("Run only the `rust` code block named NAME, as in ```rust,NAME, of the Markdown file given as <script>")
+                .long("block")
+                .value_name("NAME")
+                .num_args(1)
+                .requires("script")
+                .conflicts_with_all(["expr_or_loop", "stdin"])
+            )
+            .arg(Arg::new("repl")
+                .help("Start an interactive session, which runs each statement or expression as it is entered")
+                .long("repl")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["script", "stdin", "expr_or_loop", "pack", "eject", "fetch", "gen_pkg_only", "pkg_path", "locked", "frozen", "test", "bench"])
+            )
             .group(ArgGroup::new("expr_or_loop")
                 .args(["expr", "loop"])
             )
+            .group(ArgGroup::new("input")
+                .args(["script", "stdin", "repl"])
+                .multiple(true)
+            )
 
             // Options that impact the script being executed.
             .arg(Arg::new("base-path")
@@ -78,12 +185,26 @@ impl Args {
                 .long("base-path")
                 .num_args(1)
             )
+            .arg(Arg::new("begin")
+                .help("Run CODE before the first line of a loop. Its value is the state, which the loop closure is given as `&mut` before its other arguments")
+                .long("begin")
+                .value_name("CODE")
+                .num_args(1)
+                .requires("loop")
+            )
+            .arg(Arg::new("bytes")
+                .help("Give the loop closure each line as `&[u8]`, whether or not it is valid UTF-8")
+                .long("bytes")
+                .action(ArgAction::SetTrue)
+                .requires("loop")
+                .conflicts_with_all(["lossy", "loop_input"])
+            )
             .arg(Arg::new("cargo-output")
                 .help("Show output from cargo when building")
                 .short('c')
                 .long("cargo-output")
                 .action(ArgAction::SetTrue)
-                .requires("script")
+                .requires("input")
             )
             .arg(Arg::new("count")
                 .help("Invoke the loop closure with two arguments: line, and line number")
@@ -97,11 +218,20 @@ impl Args {
                 .action(ArgAction::SetTrue)
             )
             .arg(Arg::new("dep")
-                .help("Add a dependency - either just the package name (for the latest version) or as `name=version`")
+                .help("Add a dependency - either just the package name (for the latest version), or as `name@version`, \
+                    `name@version[feature,...]`, `name:path=DIR` or `name:git=URL#REV`. \
+                    Append `:no-default-features` to disable default features")
                 .long("dep")
                 .short('d')
                 .action(ArgAction::Append)
             )
+            .arg(Arg::new("end")
+                .help("Run CODE after the last line of a loop, and print its value. It can use the `state` from `--begin`, and the `count` of lines")
+                .long("end")
+                .value_name("CODE")
+                .num_args(1)
+                .requires("loop")
+            )
             .arg(Arg::new("extern")
                 .help("Adds an `#[macro_use] extern crate name;` item for expressions and loop scripts")
                 .long("extern")
@@ -109,6 +239,70 @@ impl Args {
                 .num_args(1..)
                 .requires("expr_or_loop")
             )
+            .arg(Arg::new("format")
+                .help("How to print the value of an expression, or of each call of a loop closure. `display` and `raw` print strings as they are, and `raw` adds no line ending")
+                .long("format")
+                .value_name("FORMAT")
+                .num_args(1)
+                .value_parser(OutputFormat::NAMES.to_vec())
+                .requires("expr_or_loop")
+            )
+            .arg(Arg::new("lossy")
+                .help("Give the loop closure each line as `Cow<str>`, with invalid UTF-8 replaced by U+FFFD")
+                .long("lossy")
+                .action(ArgAction::SetTrue)
+                .requires("loop")
+            )
+            .arg(Arg::new("null")
+                .help("Read NUL-terminated records instead of lines, as written by `find -print0`. The NUL is not part of the record")
+                .long("null")
+                .short('0')
+                .action(ArgAction::SetTrue)
+                .requires("loop")
+            )
+            .arg(Arg::new("parallel")
+                .help("Run the loop closure on N threads, or one per CPU if N is 0 or not given. Lines are read in batches, and the results are printed in order. The closure must be `Fn + Sync`")
+                .long("parallel")
+                .value_name("N")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("0")
+                .value_parser(clap::value_parser!(usize))
+                .requires("loop")
+                .conflicts_with("begin")
+            )
+            .arg(Arg::new("parse")
+                .help("Give the loop closure each line parsed into TYPE, instead of the line itself")
+                .long("parse")
+                .value_name("TYPE")
+                .num_args(1)
+                .requires("loop")
+            )
+            .arg(Arg::new("regex")
+                .help("Give the loop closure the captures of PATTERN in each line, as `&regex::Captures`, and skip lines which don't match")
+                .long("regex")
+                .value_name("PATTERN")
+                .num_args(1)
+                .requires("loop")
+            )
+            .arg(Arg::new("split")
+                .help("Give the loop closure the fields of each line, as `&[&str]`, split at DELIM, or at runs of whitespace if DELIM is a single space")
+                .long("split")
+                .short('F')
+                .value_name("DELIM")
+                .num_args(1)
+                .requires("loop")
+            )
+            .group(ArgGroup::new("loop_input")
+                .args(["parse", "regex", "split"])
+            )
+            .arg(Arg::new("type")
+                .help("Print the type of the expression, instead of its value")
+                .long("type")
+                .action(ArgAction::SetTrue)
+                .requires("expr")
+                .conflicts_with("format")
+            )
             .arg(Arg::new("unstable_features")
                 .help("Add a #![feature] declaration to the crate")
                 .long("unstable-feature")
@@ -128,21 +322,61 @@ impl Args {
                 .long("force")
                 .short('f')
                 .action(ArgAction::SetTrue)
-                .requires("script")
+                .requires("input")
+            )
+            .arg(Arg::new("locked")
+                .help("Require the script's lockfile (`SCRIPT.lock`) to be up to date")
+                .long("locked")
+                .action(ArgAction::SetTrue)
+                .requires("input")
+            )
+            .arg(Arg::new("frozen")
+                .help("Require the script's lockfile to be up to date, and don't access the network")
+                .long("frozen")
+                .action(ArgAction::SetTrue)
+                .requires("input")
+            )
+            .arg(Arg::new("offline")
+                .help("Build the script without accessing the network")
+                .long("offline")
+                .action(ArgAction::SetTrue)
+                .requires("input")
+            )
+            .arg(Arg::new("fetch")
+                .help("Generate the Cargo package and download its dependencies, but don't compile or run it")
+                .long("fetch")
+                .action(ArgAction::SetTrue)
+                .requires("input")
+                .conflicts_with_all(["gen_pkg_only", "offline", "frozen", "debug", "force", "test", "bench"])
+            )
+            .arg(Arg::new("eject")
+                .help("Write the script as a standalone Cargo project into the given directory, but don't compile or run it")
+                .long("eject")
+                .value_name("DIR")
+                .num_args(1)
+                .requires("input")
+                .conflicts_with_all(["gen_pkg_only", "pkg_path", "fetch", "debug", "force", "test", "bench"])
+            )
+            .arg(Arg::new("pack")
+                .help("Pack the Cargo project in DIR into a single script, written to OUT if it is given, or else to a file named after the package in the current directory")
+                .long("pack")
+                .value_names(["DIR", "OUT"])
+                .num_args(1..=2)
+                .conflicts_with_all(["script", "expr_or_loop", "eject", "fetch", "gen_pkg_only", "pkg_path", "debug", "force", "test", "bench"])
             )
             .arg(Arg::new("gen_pkg_only")
                 .help("Generate the Cargo package and print the path to it, but don't compile or run it")
                 .long("package")
                 .short('p')
                 .action(ArgAction::SetTrue)
-                .requires("script")
+                .requires("input")
                 .conflicts_with_all(["debug", "force", "test", "bench"])
             )
             .arg(Arg::new("pkg_path")
                 .help("Specify where to place the generated Cargo package")
                 .long("pkg-path")
                 .num_args(1)
-                .requires("script")
+                .requires("input")
                 .conflicts_with_all(["clear-cache", "force"])
             )
             .arg(Arg::new("test")
@@ -165,6 +399,13 @@ impl Args {
                 // Benchmarking currently requires nightly:
                 .conflicts_with("bench")
             )
+            .arg(Arg::new("wildcard-deps")
+                .help("What to do about dependencies which allow any version, or git dependencies without a `rev`")
+                .long("wildcard-deps")
+                .value_name("POLICY")
+                .num_args(1)
+                .value_parser(WildcardPolicy::NAMES.to_vec())
+            )
             .arg(Arg::new("wrapper")
                 .help("Wrapper injected before the command to run, e.g. 'rust-lldb' or 'hyperfine --runs 100'")
                 .long("wrapper")
@@ -197,12 +438,31 @@ impl Args {
 
         let mut m = app.get_matches();
 
+        let edit = m.remove_subcommand().map(|(name, mut m)| EditArgs {
+            command: match name.as_str() {
+                "add" => EditCommand::Add,
+                "remove" => EditCommand::Remove,
+                "upgrade" => EditCommand::Upgrade,
+                _ => unreachable!(),
+            },
+            script: m.remove_one::<String>("script").unwrap(),
+            deps: m
+                .remove_many::<String>("deps")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
+        });
+
         let script_and_args: Option<Vec<String>> = m
             .remove_many::<String>("script")
             .map(|values| values.collect());
         let script;
         let script_args: Vec<String>;
-        if let Some(script_and_args) = script_and_args {
+        let stdin = m.get_flag("stdin");
+        if stdin {
+            // All positional arguments go to the script, which is `-` in all but name.
+            script = Some("-".to_string());
+            script_args = script_and_args.unwrap_or_default();
+        } else if let Some(script_and_args) = script_and_args {
             script = script_and_args.first().map(|s| s.to_string());
             script_args = if script_and_args.len() > 1 {
                 Vec::from_iter(script_and_args[1..].iter().map(|s| s.to_string()))
@@ -213,14 +473,34 @@ impl Args {
             script = None;
             script_args = Vec::new();
         }
+        let expr = m.get_flag("expr");
+        let loop_ = m.get_flag("loop");
 
         Self {
+            stdin: stdin || (script.as_deref() == Some("-") && !expr && !loop_),
             script,
             script_args,
+            sha256: m.get_one::<String>("sha256").map(Into::into),
+            block: m.get_one::<String>("block").map(Into::into),
+            repl: m.get_flag("repl"),
 
-            expr: m.get_flag("expr"),
-            loop_: m.get_flag("loop"),
+            expr,
+            loop_,
             count: m.get_flag("count"),
+            begin: m.get_one::<String>("begin").map(Into::into),
+            end: m.get_one::<String>("end").map(Into::into),
+            split: m.get_one::<String>("split").map(Into::into),
+            regex: m.get_one::<String>("regex").map(Into::into),
+            parse: m.get_one::<String>("parse").map(Into::into),
+            bytes: m.get_flag("bytes"),
+            lossy: m.get_flag("lossy"),
+            null: m.get_flag("null"),
+            parallel: m.get_one::<usize>("parallel").copied(),
+            format: m
+                .get_one::<String>("format")
+                .and_then(|name| OutputFormat::from_name(name))
+                .unwrap_or_default(),
+            type_: m.get_flag("type"),
 
             base_path: m.get_one::<String>("base-path").map(Into::into),
             pkg_path: m.get_one::<String>("pkg_path").map(Into::into),
@@ -237,6 +517,17 @@ impl Args {
                 .map(|values| values.collect())
                 .unwrap_or_default(),
             force: m.get_flag("force"),
+            locked: m.get_flag("locked"),
+            frozen: m.get_flag("frozen"),
+            offline: m.get_flag("offline"),
+            fetch: m.get_flag("fetch"),
+            eject: m.get_one::<String>("eject").map(Into::into),
+            pack: m
+                .get_many::<String>("pack")
+                .and_then(|mut values| values.next().cloned()),
+            pack_output: m
+                .get_many::<String>("pack")
+                .and_then(|mut values| values.nth(1).cloned()),
             unstable_features: m
                 .remove_many::<String>("unstable_features")
                 .map(|values| values.collect())
@@ -248,6 +539,10 @@ impl Args {
             #[cfg(windows)]
             uninstall_file_association: m.get_flag("uninstall-file-association"),
             wrapper: m.get_one::<String>("wrapper").map(Into::into),
+            wildcard_deps: m
+                .get_one::<String>("wildcard-deps")
+                .and_then(|name| WildcardPolicy::from_name(name)),
+            edit,
         }
     }
 }
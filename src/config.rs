@@ -0,0 +1,110 @@
+/*!
+This module is concerned with the user's configuration file, which gives defaults for command-line options.
+*/
+use std::collections::HashMap;
+use std::fs;
+
+use log::info;
+
+use crate::dependency::WildcardPolicy;
+use crate::error::{MainError, MainResult};
+use crate::platform;
+
+/**
+The contents of the configuration file, `rust-script/config.toml` in the user's configuration directory.
+*/
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    /// What to do about unpinned dependencies.
+    pub wildcard_deps: Option<WildcardPolicy>,
+    /// The SHA-256 digests scripts given as URLs are pinned to.
+    pub sha256: HashMap<String, String>,
+}
+
+impl Config {
+    /// Loads the configuration file, if there is one.
+    pub fn load() -> MainResult<Self> {
+        let path = match platform::config_path() {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(MainError::Io(e)),
+        };
+        info!("using config file {:?}", path);
+
+        Self::parse(&content).map_err(|e| {
+            MainError::Tag(
+                format!("invalid config file {}", path.display()).into(),
+                Box::new(e),
+            )
+        })
+    }
+
+    fn parse(content: &str) -> MainResult<Self> {
+        let table: toml::value::Table =
+            toml::from_str(content).map_err(|e| MainError::Other(Box::new(e)))?;
+        let mut config = Self::default();
+        for (key, value) in &table {
+            match key.as_str() {
+                "wildcard-deps" => {
+                    config.wildcard_deps = Some(
+                        value
+                            .as_str()
+                            .and_then(WildcardPolicy::from_name)
+                            .ok_or_else(|| {
+                                format!(
+                                    "`wildcard-deps` must be one of {}",
+                                    WildcardPolicy::NAMES.join(", ")
+                                )
+                            })?,
+                    );
+                }
+                "sha256" => {
+                    let pins = value
+                        .as_table()
+                        .ok_or("`sha256` must be a table of script URLs and their digests")?;
+                    for (url, digest) in pins {
+                        let digest = digest
+                            .as_str()
+                            .ok_or_else(|| format!("the digest for {} must be a string", url))?;
+                        config.sha256.insert(url.clone(), digest.into());
+                    }
+                }
+                _ => info!("ignoring unknown config key `{}`", key),
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[test]
+fn test_parse_config() {
+    assert_eq!(Config::parse("").ok(), Some(Config::default()));
+    assert_eq!(
+        Config::parse("wildcard-deps = \"deny\"").ok(),
+        Some(Config {
+            wildcard_deps: Some(WildcardPolicy::Deny),
+            ..Config::default()
+        })
+    );
+    assert_eq!(
+        Config::parse("[sha256]\n\"https://example.com/a.rs\" = \"abc\"")
+            .ok()
+            .map(|config| config.sha256),
+        Some(HashMap::from([(
+            "https://example.com/a.rs".to_string(),
+            "abc".to_string()
+        )]))
+    );
+    assert_eq!(
+        Config::parse("sha256 = \"abc\"").map_err(|e| e.to_string()),
+        Err("`sha256` must be a table of script URLs and their digests".into())
+    );
+    assert_eq!(
+        Config::parse("wildcard-deps = \"never\"").map_err(|e| e.to_string()),
+        Err("`wildcard-deps` must be one of allow, warn, deny".into())
+    );
+}
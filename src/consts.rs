@@ -14,6 +14,9 @@ pub const SCRIPT_BODY_SUB: &str = "script";
 /// Substitution for the script prelude.
 pub const SCRIPT_PRELUDE_SUB: &str = "prelude";
 
+/// The template used for script file inputs that have a main function, but still need to be rewritten.
+pub const FILE_TEMPLATE: &str = "#{script}";
+
 /// The template used for script file inputs that doesn't have main function.
 pub const FILE_NO_MAIN_TEMPLATE: &str = r#"
 fn main() -> Result<(), Box<dyn std::error::Error+Sync+Send>> {
@@ -22,10 +25,13 @@ fn main() -> Result<(), Box<dyn std::error::Error+Sync+Send>> {
 }
 "#;
 
-/// The template used for `--expr` input.
+/**
+The template used for `--expr` input.
+
+The `print_output` substitution prints the value in the format asked for, unless it is `()`, see `OutputFormat::template_subs`.
+*/
 pub const EXPR_TEMPLATE: &str = r#"
 #{prelude}
-use std::any::{Any, TypeId};
 
 fn main() {
     let exit_code = match try_main() {
@@ -42,96 +48,296 @@ fn main() {
 }
 
 fn try_main() -> Result<(), Box<dyn std::error::Error>> {
-    fn _rust_script_is_empty_tuple<T: ?Sized + Any>(_s: &T) -> bool {
-        TypeId::of::<()>() == TypeId::of::<T>()
-    }
-    match {#{script}} {
-        __rust_script_expr if !_rust_script_is_empty_tuple(&__rust_script_expr) => println!("{:?}", __rust_script_expr),
-        _ => {}
-    }
+    (&Output(&{#{script}})).print_output();
     Ok(())
 }
+
+#{print_output}
 "#;
 
 /*
-Regarding the loop templates: what I *want* is for the result of the closure to be printed to standard output *only* if it's not `()`.
+Regarding the loop template: what I *want* is for the result of the closure to be printed to standard output *only* if it's not `()`, and for the other types which mean something to a filter to be treated as such: a `bool` keeps or drops the line, an `Option` or `Result` is unwrapped, and an iterator gives a line per item.
 
-* TODO: Merge the `LOOP_*` templates so there isn't duplicated code.  It's icky.
+Without specialization, this is done with one trait per type, each implemented for a different number of references to `LoopOutput`.  Calling through `&&&` makes method resolution try the impls with the most references first, so that, *e.g.*, `Option<T>` is unwrapped rather than printed as a value.
 */
 
-/// The template used for `--loop` input, assuming no `--count` flag is also given.
+/**
+The template used for `--loop` input.
+
+The `loop_*` substitutions make it into the variant for the options given, see `LoopOptions::template_subs`.  As for `EXPR_TEMPLATE`, `print_output` and `output_bound` come from `OutputFormat::template_subs`.
+*/
 pub const LOOP_TEMPLATE: &str = r#"
 #![allow(unused_imports)]
 #![allow(unused_braces)]
+#![allow(unused_variables, unused_assignments, unused_mut, dead_code)]
 #{prelude}
 use std::any::Any;
 use std::io::prelude::*;
 
 fn main() {
-    let mut closure = enforce_closure(
+    let mut state = {#{loop_begin}};
+    let mut closure = enforce_closure(&state,
 {#{script}}
     );
-    let mut line_buffer = String::new();
+    #{loop_setup}
+    let mut buffer = Vec::new();
     let stdin = std::io::stdin();
+    let mut count = 0;
+    let mut failed = false;
     loop {
-        line_buffer.clear();
-        let read_res = stdin.read_line(&mut line_buffer).unwrap_or(0);
-        if read_res == 0 { break }
-        let output = closure(&line_buffer);
-
-        let display = {
-            let output_any: &dyn Any = &output;
-            !output_any.is::<()>()
-        };
-
-        if display {
-            println!("{:?}", output);
+        buffer.clear();
+        match stdin.lock().read_until(#{loop_separator}, &mut buffer) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("error: could not read from stdin: {}", e);
+                std::process::exit(1);
+            }
         }
+        count += 1;
+        #{loop_record}
+        #{loop_input}
+        let output = closure(#{loop_state_arg}input#{loop_count_arg});
+        if !(&&&LoopOutput::new(output)).print_loop_output(&buffer, count) {
+            failed = true;
+        }
+    }
+    let output = {#{loop_end}};
+    (&Output(&output)).print_output();
+    if failed {
+        std::process::exit(1);
     }
 }
 
-fn enforce_closure<F, T>(closure: F) -> F
-where F: FnMut(&str) -> T, T: 'static {
+fn enforce_closure<S, F, T>(_state: &S, closure: F) -> F
+where F: FnMut(#{loop_state_param}#{loop_input_type}#{loop_count_param}) -> T, T: 'static {
     closure
 }
+
+#{loop_output}
+
+#{print_output}
 "#;
 
-/// The template used for `--count --loop` input.
-pub const LOOP_COUNT_TEMPLATE: &str = r#"
+/**
+The template used for `--loop` input with `--parallel`.
+
+Records are read in batches, each of which is split between the threads, and the results are printed in the order the records were read.  The substitutions are the same as for `LOOP_TEMPLATE`, with `loop_threads` being the number of threads, or `0` for one per CPU.
+*/
+pub const PARALLEL_LOOP_TEMPLATE: &str = r#"
 #![allow(unused_imports)]
 #![allow(unused_braces)]
+#![allow(unused_variables, unused_assignments, unused_mut, dead_code)]
+#{prelude}
 use std::any::Any;
 use std::io::prelude::*;
 
 fn main() {
-    let mut closure = enforce_closure(
+    let state = {#{loop_begin}};
+    let closure = enforce_closure(
 {#{script}}
     );
-    let mut line_buffer = String::new();
+    #{loop_setup}
+    let threads = match #{loop_threads} {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let batch_len = threads * 256;
     let stdin = std::io::stdin();
+    let mut stdin = stdin.lock();
+    let mut records: Vec<Vec<u8>> = Vec::with_capacity(batch_len);
     let mut count = 0;
-    loop {
-        line_buffer.clear();
-        let read_res = stdin.read_line(&mut line_buffer).unwrap_or(0);
-        if read_res == 0 { break }
-        count += 1;
-        let output = closure(&line_buffer, count);
+    let mut failed = false;
+    let mut done = false;
+    while !done {
+        records.clear();
+        while records.len() < batch_len {
+            let mut buffer = Vec::new();
+            match stdin.read_until(#{loop_separator}, &mut buffer) {
+                Ok(0) => {
+                    done = true;
+                    break;
+                }
+                Ok(_) => records.push(buffer),
+                Err(e) => {
+                    eprintln!("error: could not read from stdin: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if records.is_empty() {
+            break;
+        }
 
-        let display = {
-            let output_any: &dyn Any = &output;
-            !output_any.is::<()>()
-        };
+        let first = count + 1;
+        let chunk_len = (records.len() + threads - 1) / threads;
+        // Each result is what the closure returned, or the message and exit code to stop with.
+        let results: Vec<Vec<(usize, Result<_, (String, i32)>)>> = std::thread::scope(|scope| {
+            let closure = &closure;
+            let workers: Vec<_> = records
+                .chunks(chunk_len)
+                .enumerate()
+                .map(|(chunk_index, chunk)| {
+                    scope.spawn(move || {
+                        let mut results = Vec::with_capacity(chunk.len());
+                        for (index, buffer) in chunk.iter().enumerate() {
+                            let count = first + chunk_index * chunk_len + index;
+                            #{loop_record}
+                            #{loop_input}
+                            let output = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                                closure(input#{loop_count_arg})
+                            }));
+                            match output {
+                                Ok(output) => results.push((count, Ok(output))),
+                                Err(_) => {
+                                    // The panic hook has already printed the message.
+                                    let message = format!("error: the closure panicked on #{loop_what} {}", count);
+                                    results.push((count, Err((message, 101))));
+                                    break;
+                                }
+                            }
+                        }
+                        results
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("panics are caught in the workers"))
+                .collect()
+        });
+        count += records.len();
 
-        if display {
-            println!("{:?}", output);
+        for (count, output) in results.into_iter().flatten() {
+            match output {
+                Ok(output) => {
+                    if !(&&&LoopOutput::new(output)).print_loop_output(&records[count - first], count) {
+                        failed = true;
+                    }
+                }
+                Err((message, code)) => {
+                    eprintln!("{}", message);
+                    std::process::exit(code);
+                }
+            }
         }
     }
+    let output = {#{loop_end}};
+    (&Output(&output)).print_output();
+    if failed {
+        std::process::exit(1);
+    }
 }
 
 fn enforce_closure<F, T>(closure: F) -> F
-where F: FnMut(&str, usize) -> T, T: 'static {
+where F: Fn(#{loop_input_type}#{loop_count_param}) -> T + Sync, T: Send + 'static {
     closure
 }
+
+#{loop_output}
+
+#{print_output}
+"#;
+
+/**
+The items which print what a loop closure returns for each line, substituted into `LOOP_TEMPLATE` and `PARALLEL_LOOP_TEMPLATE` as `loop_output`.
+*/
+pub const LOOP_OUTPUT_ITEMS: &str = r#"
+/// Holds what the closure returned for a line, which is printed according to its type, see `print_loop_output`.
+struct LoopOutput<T>(std::cell::Cell<Option<T>>);
+
+impl<T> LoopOutput<T> {
+    fn new(output: T) -> Self {
+        LoopOutput(std::cell::Cell::new(Some(output)))
+    }
+
+    fn take(&self) -> T {
+        self.0.take().unwrap()
+    }
+}
+
+trait SkipUnitOutput {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool;
+}
+
+impl SkipUnitOutput for &&LoopOutput<()> {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool {
+        true
+    }
+}
+
+trait FilterOutput {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool;
+}
+
+impl FilterOutput for &&LoopOutput<bool> {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool {
+        if self.take() {
+            let mut stdout = std::io::stdout().lock();
+            let _ = stdout.write_all(record);
+            if !record.ends_with(&[#{loop_separator}]) {
+                let _ = stdout.write_all(&[#{loop_separator}]);
+            }
+        }
+        true
+    }
+}
+
+trait OptionOutput {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool;
+}
+
+impl<T: #{output_bound}> OptionOutput for &&LoopOutput<Option<T>> {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool {
+        if let Some(value) = self.take() {
+            print_value(&value);
+        }
+        true
+    }
+}
+
+trait ResultOutput {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool;
+}
+
+impl<T: #{output_bound}, E: std::fmt::Display> ResultOutput for &&LoopOutput<Result<T, E>> {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool {
+        match self.take() {
+            Ok(value) => {
+                print_value(&value);
+                true
+            }
+            Err(e) => {
+                eprintln!("error: #{loop_what} {}: {}", count, e);
+                false
+            }
+        }
+    }
+}
+
+trait IteratorOutput {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool;
+}
+
+impl<I: Iterator> IteratorOutput for &LoopOutput<I> where I::Item: #{output_bound} {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool {
+        for value in self.take() {
+            print_value(&value);
+        }
+        true
+    }
+}
+
+trait ValueOutput {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool;
+}
+
+impl<T: #{output_bound}> ValueOutput for LoopOutput<T> {
+    fn print_loop_output(&self, record: &[u8], count: usize) -> bool {
+        print_value(&self.take());
+        true
+    }
+}
 "#;
 
 /**
@@ -0,0 +1,376 @@
+/*!
+This module is concerned with parsing dependencies given on the command line.
+*/
+use regex::Regex;
+use std::path::Path;
+
+use crate::error::MainResult;
+
+/**
+A dependency given with `--dep`.
+
+The accepted forms are:
+
+- `name`, for any version.
+- `name=version` or `name={ ... }`, which is pasted into the manifest as-is.
+- `name@version`, optionally followed by `[feature,...]`.
+- `name:path=DIR` and `name:git=URL#REV`, optionally with features.
+- Any of the non-`=` forms followed by `:no-default-features`.
+*/
+#[derive(Debug, Default, PartialEq)]
+pub struct DepSpec {
+    pub name: String,
+    pub version: Option<String>,
+    pub features: Vec<String>,
+    pub default_features: bool,
+    pub source: Option<DepSource>,
+    /// The value given with the legacy `name=...` form.
+    pub raw: Option<String>,
+}
+
+/// Where a dependency comes from, if not from the registry.
+#[derive(Debug, PartialEq)]
+pub enum DepSource {
+    Path(String),
+    Git { url: String, rev: Option<String> },
+}
+
+/**
+What to do about dependencies that aren't pinned: those which allow any version, and git dependencies without a `rev`.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WildcardPolicy {
+    #[default]
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl WildcardPolicy {
+    pub const NAMES: &'static [&'static str] = &["allow", "warn", "deny"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "allow" => Some(Self::Allow),
+            "warn" => Some(Self::Warn),
+            "deny" => Some(Self::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// Options which can follow a dependency, introduced by `:`.
+const OPTIONS: &[&str] = &["no-default-features", "path=", "git="];
+
+impl DepSpec {
+    /**
+    Parses a dependency specification.
+
+    Relative `path` dependencies are resolved against `cwd`, since that is what they are relative to when typed into a shell.
+    */
+    pub fn parse(spec: &str, cwd: &Path) -> MainResult<Self> {
+        let err = |msg: &str| format!("invalid dependency `{}`: {}", spec, msg);
+
+        let name_end = spec.find(['=', '@', '[', ':']).unwrap_or(spec.len());
+        let name = &spec[..name_end];
+        if name.is_empty() {
+            return Err(err("missing package name").into());
+        }
+        if !is_valid_name(name) {
+            return Err(err(
+                "package names may only contain letters, numbers, `-` and `_`, and must start with a letter or `_`",
+            )
+            .into());
+        }
+
+        let mut dep = DepSpec {
+            name: name.into(),
+            default_features: true,
+            ..Default::default()
+        };
+        let mut rest = &spec[name_end..];
+
+        // The original `name=version` form, which takes anything after the `=` as-is.
+        if let Some(value) = rest.strip_prefix('=') {
+            if value.is_empty() {
+                return Err(err("missing version").into());
+            }
+            if !value.starts_with('{') && !is_valid_version_req(value) {
+                return Err(err(&format!("`{}` is not a valid version requirement", value)).into());
+            }
+            dep.raw = Some(value.into());
+            return Ok(dep);
+        }
+
+        if let Some(after_at) = rest.strip_prefix('@') {
+            let version_end = after_at.find(['[', ':']).unwrap_or(after_at.len());
+            let version = &after_at[..version_end];
+            if version.is_empty() {
+                return Err(err("missing version after `@`").into());
+            }
+            if !is_valid_version_req(version) {
+                return Err(
+                    err(&format!("`{}` is not a valid version requirement", version)).into(),
+                );
+            }
+            dep.version = Some(version.into());
+            rest = &after_at[version_end..];
+        }
+
+        if let Some(after_bracket) = rest.strip_prefix('[') {
+            let list_end = after_bracket
+                .find(']')
+                .ok_or_else(|| err("missing `]` after features"))?;
+            for feature in after_bracket[..list_end].split(',').map(str::trim) {
+                if !is_valid_feature(feature) {
+                    return Err(err(&format!("`{}` is not a valid feature name", feature)).into());
+                }
+                dep.features.push(feature.into());
+            }
+            rest = &after_bracket[list_end + 1..];
+        }
+
+        while let Some(after_colon) = rest.strip_prefix(':') {
+            // Values may contain colons themselves (URLs, Windows paths), so only split where another option starts.
+            let value_end = (0..after_colon.len())
+                .filter(|&i| after_colon.is_char_boundary(i))
+                .find(|&i| {
+                    after_colon[i..].starts_with(':')
+                        && OPTIONS.iter().any(|o| after_colon[i + 1..].starts_with(o))
+                })
+                .unwrap_or(after_colon.len());
+            let option = &after_colon[..value_end];
+            rest = &after_colon[value_end..];
+
+            if option == "no-default-features" {
+                dep.default_features = false;
+                continue;
+            }
+
+            if dep.source.is_some() {
+                return Err(err("only one of `path` and `git` may be given").into());
+            }
+            if let Some(path) = option.strip_prefix("path=") {
+                if path.is_empty() {
+                    return Err(err("missing path").into());
+                }
+                let path = cwd.join(path);
+                let path = path
+                    .to_str()
+                    .ok_or_else(|| err("path is not valid UTF-8"))?;
+                dep.source = Some(DepSource::Path(path.into()));
+            } else if let Some(git) = option.strip_prefix("git=") {
+                let (url, rev) = match git.rsplit_once('#') {
+                    Some((url, rev)) if !rev.is_empty() => (url, Some(rev.into())),
+                    Some(_) => return Err(err("missing revision after `#`").into()),
+                    None => (git, None),
+                };
+                if url.is_empty() {
+                    return Err(err("missing git URL").into());
+                }
+                dep.source = Some(DepSource::Git {
+                    url: url.into(),
+                    rev,
+                });
+            } else {
+                return Err(err(&format!(
+                    "unknown option `{}`; expected `path=`, `git=` or `no-default-features`",
+                    option
+                ))
+                .into());
+            }
+        }
+
+        if !rest.is_empty() {
+            return Err(err(&format!("unexpected `{}`", rest)).into());
+        }
+
+        Ok(dep)
+    }
+
+    /**
+    Renders the value of the dependency's entry in `[dependencies]`.
+
+    This is either a bare version requirement, or an inline table starting with `{`.
+    */
+    pub fn to_manifest_value(&self) -> String {
+        if let Some(raw) = &self.raw {
+            return raw.clone();
+        }
+
+        let version = match (&self.version, &self.source) {
+            (Some(version), _) => Some(version.as_str()),
+            (None, None) => Some("*"),
+            (None, Some(_)) => None,
+        };
+
+        if self.features.is_empty() && self.default_features && self.source.is_none() {
+            return version.unwrap_or("*").into();
+        }
+
+        let mut entries: Vec<(&str, toml::Value)> = Vec::new();
+        if let Some(version) = version {
+            entries.push(("version", version.into()));
+        }
+        match &self.source {
+            Some(DepSource::Path(path)) => entries.push(("path", path.as_str().into())),
+            Some(DepSource::Git { url, rev }) => {
+                entries.push(("git", url.as_str().into()));
+                if let Some(rev) = rev {
+                    entries.push(("rev", rev.as_str().into()));
+                }
+            }
+            None => {}
+        }
+        if !self.features.is_empty() {
+            entries.push(("features", self.features.clone().into()));
+        }
+        if !self.default_features {
+            entries.push(("default-features", false.into()));
+        }
+
+        let entries: Vec<String> = entries
+            .into_iter()
+            .map(|(k, v)| format!("{} = {}", k, v))
+            .collect();
+        format!("{{ {} }}", entries.join(", "))
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let re: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_-]*$").unwrap();
+    re.is_match(name)
+}
+
+fn is_valid_feature(feature: &str) -> bool {
+    let re: Regex = Regex::new(r"^[A-Za-z0-9_][A-Za-z0-9_+./-]*$").unwrap();
+    re.is_match(feature)
+}
+
+/**
+Checks that the given string looks like a Cargo version requirement, *e.g.* `1`, `^1.2.3`, `>=1.2, <1.5` or `*`.
+*/
+pub fn is_valid_version_req(req: &str) -> bool {
+    let re: Regex = Regex::new(
+        r"(?x)
+            ^\s*
+            (=|>=|<=|>|<|~|\^)?\s*
+            (
+                \*
+                | \d+ (\.(\d+|\*|x|X) (\.(\d+|\*|x|X))? )?
+                  (-[0-9A-Za-z.-]+)?
+                  (\+[0-9A-Za-z.-]+)?
+            )
+            \s*$
+        ",
+    )
+    .unwrap();
+    req.split(',').all(|comparator| re.is_match(comparator))
+}
+
+#[test]
+fn test_dep_spec() {
+    let cwd = Path::new("/cwd");
+    macro_rules! ds {
+        ($s:expr) => {
+            DepSpec::parse($s, cwd)
+                .map(|d| (d.name.clone(), d.to_manifest_value()))
+                .map_err(|e| e.to_string())
+        };
+    }
+    macro_rules! ok {
+        ($n:expr, $v:expr) => {
+            Ok(($n.to_string(), $v.to_string()))
+        };
+    }
+
+    assert_eq!(ds!("serde"), ok!("serde", "*"));
+    assert_eq!(ds!("serde=1.0.100"), ok!("serde", "1.0.100"));
+    assert_eq!(
+        ds!(r#"serde={version="1",features=["derive"]}"#),
+        ok!("serde", r#"{version="1",features=["derive"]}"#)
+    );
+    assert_eq!(ds!("serde@^1.2"), ok!("serde", "^1.2"));
+    assert_eq!(ds!("serde@>=1.2, <1.5"), ok!("serde", ">=1.2, <1.5"));
+    assert_eq!(
+        ds!("serde@1[derive,rc]"),
+        ok!("serde", r#"{ version = "1", features = ["derive", "rc"] }"#)
+    );
+    assert_eq!(
+        ds!("serde[derive]"),
+        ok!("serde", r#"{ version = "*", features = ["derive"] }"#)
+    );
+    assert_eq!(
+        ds!("mylib:path=../crate"),
+        ok!("mylib", r#"{ path = "/cwd/../crate" }"#)
+    );
+    assert_eq!(
+        ds!("mylib[fancy]:path=/abs/crate"),
+        ok!("mylib", r#"{ path = "/abs/crate", features = ["fancy"] }"#)
+    );
+    assert_eq!(
+        ds!("mylib:git=https://example.com/mylib.git#abc123"),
+        ok!(
+            "mylib",
+            r#"{ git = "https://example.com/mylib.git", rev = "abc123" }"#
+        )
+    );
+    assert_eq!(
+        ds!("mylib:git=https://example.com/mylib.git:no-default-features"),
+        ok!(
+            "mylib",
+            r#"{ git = "https://example.com/mylib.git", default-features = false }"#
+        )
+    );
+    assert_eq!(
+        ds!("serde@1:no-default-features"),
+        ok!("serde", r#"{ version = "1", default-features = false }"#)
+    );
+
+    assert_eq!(
+        ds!("=1"),
+        Err("invalid dependency `=1`: missing package name".into())
+    );
+    assert_eq!(
+        ds!("serde="),
+        Err("invalid dependency `serde=`: missing version".into())
+    );
+    assert_eq!(
+        ds!("serde@one"),
+        Err("invalid dependency `serde@one`: `one` is not a valid version requirement".into())
+    );
+    assert_eq!(
+        ds!("serde=1.0.100=2"),
+        Err(
+            "invalid dependency `serde=1.0.100=2`: `1.0.100=2` is not a valid version requirement"
+                .into()
+        )
+    );
+    assert_eq!(
+        ds!("serde@1[derive"),
+        Err("invalid dependency `serde@1[derive`: missing `]` after features".into())
+    );
+    assert_eq!(
+        ds!("serde@1[derive,]"),
+        Err("invalid dependency `serde@1[derive,]`: `` is not a valid feature name".into())
+    );
+    assert_eq!(
+        ds!("serde@1:branch=main"),
+        Err("invalid dependency `serde@1:branch=main`: unknown option `branch=main`; expected `path=`, `git=` or `no-default-features`".into())
+    );
+    assert_eq!(
+        ds!("mylib:path=a:git=b"),
+        Err(
+            "invalid dependency `mylib:path=a:git=b`: only one of `path` and `git` may be given"
+                .into()
+        )
+    );
+    assert_eq!(
+        ds!("serde@1]"),
+        Err("invalid dependency `serde@1]`: `1]` is not a valid version requirement".into())
+    );
+    assert_eq!(
+        ds!("my lib"),
+        Err("invalid dependency `my lib`: package names may only contain letters, numbers, `-` and `_`, and must start with a letter or `_`".into())
+    );
+}
@@ -0,0 +1,679 @@
+/*!
+This module is concerned with editing the dependencies in a script's embedded manifest, without disturbing the rest of the script.
+*/
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::dependency::{DepSource, DepSpec};
+use crate::error::{MainError, MainResult};
+use crate::manifest::{self, DepListArg, DepListValue, Manifest};
+
+/// Which of the editing subcommands to run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditCommand {
+    Add,
+    Remove,
+    Upgrade,
+}
+
+/// A single change to the dependencies of a script.
+#[derive(Debug)]
+enum DepEdit {
+    /// Adds the dependency, replacing it if it is already there.
+    Set(DepSpec),
+    Remove(String),
+    /// Changes the version requirement of a dependency in the given table, such as `dev-dependencies`.
+    SetVersion(&'static str, String, String),
+}
+
+/// The tables of the manifest which `upgrade` raises requirements in.
+const UPGRADED_TABLES: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/**
+Runs one of the editing subcommands on the given script, rewriting it in place.
+
+`deps` are dependency specifications for `add`, and names of dependencies for `remove` and `upgrade`.
+*/
+pub fn edit_script(command: EditCommand, script: &str, deps: &[String]) -> MainResult<()> {
+    let (path, _) =
+        crate::find_script(script.as_ref()).ok_or(format!("could not find script: {}", script))?;
+    if manifest::is_markdown(&path) {
+        return Err(
+            "cannot edit the dependencies of a Markdown script: edit its `cargo` code block instead"
+                .into(),
+        );
+    }
+    let content = fs::read_to_string(&path)?;
+    let cwd = std::env::current_dir()?;
+    let script_dir = cwd
+        .join(&path)
+        .parent()
+        .expect("couldn't get parent directory for script")
+        .to_path_buf();
+
+    let edits = match command {
+        EditCommand::Add => deps
+            .iter()
+            .map(|dep| {
+                let mut dep = DepSpec::parse(dep, &cwd)?;
+                // Paths in the script are relative to the script.
+                if let Some(DepSource::Path(dep_path)) = &mut dep.source {
+                    *dep_path = manifest::relative_path(Path::new(dep_path), &script_dir)
+                        .to_string_lossy()
+                        .replace('\\', "/");
+                }
+                if dep.version.is_none() && dep.source.is_none() && dep.raw.is_none() {
+                    // Rather than adding a requirement which allows any version.
+                    dep.version = Some(latest_version(&dep.name).ok_or_else(|| {
+                        format!(
+                            "could not find `{0}` in the local registry index: give its version, as in `{0}@1`",
+                            dep.name
+                        )
+                    })?);
+                }
+                eprintln!("Adding {} to {}", dep.name, path.display());
+                Ok(DepEdit::Set(dep))
+            })
+            .collect::<MainResult<Vec<_>>>()?,
+        EditCommand::Remove => deps
+            .iter()
+            .map(|name| {
+                eprintln!("Removing {} from {}", name, path.display());
+                DepEdit::Remove(name.clone())
+            })
+            .collect(),
+        EditCommand::Upgrade => {
+            let mut edits = Vec::new();
+            for (table, name, package, req) in registry_dependencies(&content, &path)? {
+                if !deps.is_empty() && !deps.contains(&name) {
+                    continue;
+                }
+                let latest = match latest_version(&package) {
+                    Some(latest) => latest,
+                    None => {
+                        eprintln!("Skipping {}: not found in the local registry index", name);
+                        continue;
+                    }
+                };
+                if let Some(new_req) = raise_requirement(&req, &latest) {
+                    let table_note = match table {
+                        "dependencies" => String::new(),
+                        table => format!(" in [{}]", table),
+                    };
+                    eprintln!("Upgrading {}{}: {} -> {}", name, table_note, req, new_req);
+                    edits.push(DepEdit::SetVersion(table, name, new_req));
+                }
+            }
+            if edits.is_empty() {
+                eprintln!("All dependencies are up to date");
+            }
+            edits
+        }
+    };
+
+    let mut new_content = content.clone();
+    for edit in &edits {
+        new_content = apply_edit(&new_content, edit)?;
+    }
+    if new_content != content {
+        info!("writing {:?}", path);
+        fs::write(&path, new_content)?;
+    }
+    Ok(())
+}
+
+/**
+Applies an edit to the script, through whichever form of embedded manifest it has.
+
+A script without a manifest gets a short comment manifest.
+*/
+fn apply_edit(script: &str, edit: &DepEdit) -> MainResult<String> {
+    let content = manifest::strip_shebang(script);
+    match manifest::find_embedded_manifest(content) {
+        Some((Manifest::Frontmatter(body), _)) => {
+            let new_body = edit_toml(body, edit)?;
+            splice(script, body, &new_body)
+        }
+        Some((Manifest::TomlOwned(toml, origins), _)) => {
+            let (first, last) = match (origins.first(), origins.last()) {
+                (Some((_, first)), Some((_, last))) => (*first, *last),
+                _ => return Err("cannot edit an empty `cargo` code block".into()),
+            };
+            let start = offset_in(script, first)?;
+            let line_start = script[..start].rfind('\n').map_or(0, |i| i + 1);
+            let end = offset_in(script, last)?;
+            let line_end = script[end..]
+                .find('\n')
+                .map_or(script.len(), |i| end + i + 1);
+
+            // Keep the comment markers and indentation of the first line of the block.
+            let prefix = &script[line_start..start];
+            let new_toml = edit_toml(&toml, edit)?;
+            let mut block = String::new();
+            for line in new_toml.lines() {
+                block.push_str(format!("{}{}", prefix, line).trim_end());
+                block.push('\n');
+            }
+            splice(script, &script[line_start..line_end], &block)
+        }
+        Some((Manifest::DepList(lines), _)) => edit_dep_list(script, &lines, edit),
+        Some((Manifest::Toml(_), _)) | None => match edit {
+            DepEdit::Set(dep) => {
+                let line = format!("// cargo-deps: {}\n", dep_list_entry(dep));
+                splice(script, &content[..0], &line)
+            }
+            DepEdit::Remove(name) | DepEdit::SetVersion(_, name, _) => Err(not_found(name)),
+        },
+    }
+}
+
+/// Applies an edit to a TOML manifest, keeping its formatting.
+fn edit_toml(toml: &str, edit: &DepEdit) -> MainResult<String> {
+    let mut doc: toml_edit::DocumentMut = toml.parse().map_err(|e| {
+        MainError::Tag(
+            "could not parse embedded manifest".into(),
+            Box::new(MainError::Other(Box::new(e))),
+        )
+    })?;
+    let table = match edit {
+        DepEdit::SetVersion(table, _, _) => table,
+        _ => "dependencies",
+    };
+    let deps = doc
+        .entry(table)
+        .or_insert(toml_edit::table())
+        .as_table_like_mut()
+        .ok_or_else(|| format!("`{}` in the embedded manifest is not a table", table))?;
+
+    match edit {
+        DepEdit::Set(dep) => {
+            let value = dep.to_manifest_value();
+            let value: toml_edit::Value = if value.starts_with('{') {
+                value.parse().map_err(|e| MainError::Other(Box::new(e)))?
+            } else {
+                value.into()
+            };
+            deps.insert(&dep.name, toml_edit::Item::Value(value));
+        }
+        DepEdit::Remove(name) => {
+            deps.remove(name).ok_or_else(|| not_found(name))?;
+        }
+        DepEdit::SetVersion(_, name, version) => {
+            let item = deps.get_mut(name).ok_or_else(|| not_found(name))?;
+            match item.as_value_mut() {
+                Some(value) if value.is_str() => {
+                    let decor = value.decor().clone();
+                    *value = version.into();
+                    *value.decor_mut() = decor;
+                }
+                _ => {
+                    let table = item.as_table_like_mut().ok_or_else(|| {
+                        format!("dependency `{}` is neither a version nor a table", name)
+                    })?;
+                    match table.get_mut("version").and_then(|v| v.as_value_mut()) {
+                        Some(value) => {
+                            let decor = value.decor().clone();
+                            *value = version.into();
+                            *value.decor_mut() = decor;
+                        }
+                        None => {
+                            table.insert("version", toml_edit::value(version));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(doc.to_string())
+}
+
+/// Applies an edit to a short comment manifest, changing only the entry concerned.
+fn edit_dep_list(script: &str, lines: &[&str], edit: &DepEdit) -> MainResult<String> {
+    let mut entries = Vec::new();
+    for line in lines {
+        for entry in manifest::parse_dep_list_line(line)
+            .map_err(|(msg, _)| format!("could not parse embedded manifest: {}", msg))?
+        {
+            entries.push((*line, entry));
+        }
+    }
+    let name = match edit {
+        DepEdit::Set(dep) => &dep.name,
+        DepEdit::Remove(name) | DepEdit::SetVersion(_, name, _) => name,
+    };
+    let found = entries.iter().find(|(_, entry)| entry.name == name);
+
+    match (edit, found) {
+        (DepEdit::Set(dep), Some((_, entry))) => splice(script, entry.span, &dep_list_entry(dep)),
+        (DepEdit::Set(dep), None) => {
+            let last = lines
+                .last()
+                .expect("short comment manifests have at least one line");
+            let content = last.trim_end();
+            let sep = if content.trim().is_empty() { " " } else { ", " };
+            splice(
+                script,
+                &last[content.len()..content.len()],
+                &format!("{}{}", sep, dep_list_entry(dep)),
+            )
+        }
+        (DepEdit::Remove(_), Some((line, entry))) => {
+            if entries
+                .iter()
+                .filter(|(l, _)| l.as_ptr() == line.as_ptr())
+                .count()
+                == 1
+            {
+                // The whole line goes.
+                let start = offset_in(script, line)?;
+                let line_start = script[..start].rfind('\n').map_or(0, |i| i + 1);
+                let line_end = script[start..]
+                    .find('\n')
+                    .map_or(script.len(), |i| start + i + 1);
+                return splice(script, &script[line_start..line_end], "");
+            }
+
+            // Take a comma along, from after the entry if there is one, or else from before it.
+            let entry_start = offset_in(line, entry.span)?;
+            let entry_end = entry_start + entry.span.len();
+            let after = &line[entry_end..];
+            let removed = if let Some(rest) = after.trim_start().strip_prefix(',') {
+                let end = line.len() - rest.trim_start().len();
+                &line[entry_start..end]
+            } else {
+                let before = line[..entry_start].trim_end();
+                let start = before.strip_suffix(',').map_or(entry_start, |b| b.len());
+                &line[start..entry_end]
+            };
+            splice(script, removed, "")
+        }
+        (DepEdit::SetVersion(_, _, version), Some((_, entry))) => {
+            let version_value = entry.value.as_ref().map(|value| match value {
+                DepListValue::Version(v) => (*v, false),
+                DepListValue::Table(t) => (*t, true),
+            });
+            let version_key = entry.args.iter().find_map(|arg| match arg {
+                DepListArg::Key("version", value) => Some(*value),
+                _ => None,
+            });
+            match (version_value, version_key) {
+                (Some((table, true)), _) => {
+                    let mut doc: toml_edit::DocumentMut = format!("dep = {}", table)
+                        .parse()
+                        .map_err(|e| MainError::Other(Box::new(e)))?;
+                    let new_table = edit_toml(
+                        &format!("[dependencies]\n{}", doc),
+                        &DepEdit::SetVersion("dependencies", "dep".into(), version.clone()),
+                    )?;
+                    doc = new_table
+                        .parse()
+                        .map_err(|e| MainError::Other(Box::new(e)))?;
+                    let value = doc["dependencies"]["dep"].to_string();
+                    splice(script, table, value.trim())
+                }
+                (Some((old, false)), _) | (None, Some(old)) => {
+                    let quote = if old.starts_with(['"', '\'']) {
+                        "\""
+                    } else {
+                        ""
+                    };
+                    splice(script, old, &format!("{}{}{}", quote, version, quote))
+                }
+                (None, None) => {
+                    let end = &entry.span[entry.span.len()..];
+                    splice(script, end, &format!("=\"{}\"", version))
+                }
+            }
+        }
+        (DepEdit::Remove(name), None) | (DepEdit::SetVersion(_, name, _), None) => {
+            Err(not_found(name))
+        }
+    }
+}
+
+/// Renders a dependency for a short comment manifest.
+fn dep_list_entry(dep: &DepSpec) -> String {
+    let mut args: Vec<String> = dep.features.clone();
+    match &dep.source {
+        Some(DepSource::Path(path)) => args.push(format!("path={:?}", path)),
+        Some(DepSource::Git { url, rev }) => {
+            args.push(format!("git={:?}", url));
+            if let Some(rev) = rev {
+                args.push(format!("rev={:?}", rev));
+            }
+        }
+        None => {}
+    }
+    if !dep.default_features {
+        args.push("default-features=false".into());
+    }
+
+    let mut entry = dep.name.clone();
+    if !args.is_empty() {
+        entry.push_str(&format!("({})", args.join(", ")));
+    }
+    match (&dep.raw, &dep.version) {
+        (Some(raw), _) if raw.starts_with('{') => entry.push_str(&format!("={}", raw)),
+        (Some(version), _) | (None, Some(version)) => entry.push_str(&format!("=\"{}\"", version)),
+        (None, None) if dep.source.is_none() => entry.push_str("=\"*\""),
+        (None, None) => {}
+    }
+    entry
+}
+
+/// Replaces `piece`, which must be a slice of `s`, with `with`.
+fn splice(s: &str, piece: &str, with: &str) -> MainResult<String> {
+    let start = offset_in(s, piece)?;
+    Ok(format!(
+        "{}{}{}",
+        &s[..start],
+        with,
+        &s[start + piece.len()..]
+    ))
+}
+
+/// Returns the offset of `piece`, which must be a slice of `s`.
+fn offset_in(s: &str, piece: &str) -> MainResult<usize> {
+    manifest::offset_of(s, piece)
+        .ok_or_else(|| "could not find the embedded manifest in the script".into())
+}
+
+fn not_found(name: &str) -> MainError {
+    format!("dependency `{}` not found in the embedded manifest", name).into()
+}
+
+/**
+Returns the table, name, package name and version requirement of each dependency in the script that comes from the registry, from the tables in `UPGRADED_TABLES`.
+*/
+fn registry_dependencies(
+    script: &str,
+    path: &Path,
+) -> MainResult<Vec<(&'static str, String, String, String)>> {
+    let content = manifest::strip_shebang(script);
+    let mani = match manifest::find_embedded_manifest(content) {
+        Some((mani, _)) => mani.into_toml(script, &path.display().to_string())?,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut deps = Vec::new();
+    for table_name in UPGRADED_TABLES {
+        let Some(toml::Value::Table(table)) = mani.get(table_name) else {
+            continue;
+        };
+        for (name, value) in table {
+            match value {
+                toml::Value::String(req) => {
+                    deps.push((table_name, name.clone(), name.clone(), req.clone()))
+                }
+                toml::Value::Table(dep) => {
+                    if ["path", "git", "registry"]
+                        .iter()
+                        .any(|k| dep.contains_key(*k))
+                    {
+                        continue;
+                    }
+                    if let Some(req) = dep.get("version").and_then(|v| v.as_str()) {
+                        let package = dep.get("package").and_then(|p| p.as_str()).unwrap_or(name);
+                        deps.push((table_name, name.clone(), package.into(), req.into()));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(deps)
+}
+
+/**
+Raises a version requirement so that it requires `latest`, keeping its operator and precision.
+
+A requirement which already allows `latest` is raised too: `^1.2` becomes `^1.5` for `1.5.0`.  Returns `None` if the requirement is already as high as `latest` at its own precision (`1` for `1.0.219`), or is a range that can't be raised this way.
+*/
+fn raise_requirement(req: &str, latest: &str) -> Option<String> {
+    let re_req: Regex = Regex::new(r"^\s*(\^|~|=)?\s*(\d+(?:\.\d+){0,2})\s*$").unwrap();
+    let cap = re_req.captures(req)?;
+    let op = cap.get(1).map_or("", |m| m.as_str());
+    let old: Vec<u64> = cap[2]
+        .split('.')
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    let new: Vec<u64> = parse_version(latest)?.into_iter().take(old.len()).collect();
+    if new <= old {
+        return None;
+    }
+    let new: Vec<String> = new.iter().map(u64::to_string).collect();
+    Some(format!("{}{}", op, new.join(".")))
+}
+
+/// Parses a plain `major.minor.patch` version, ignoring build metadata.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    let version = version.split('+').next()?;
+    version.split('.').map(|n| n.parse().ok()).collect()
+}
+
+/**
+Looks up the latest version of a crate which is neither yanked nor a pre-release in the local copy of the registry index.
+*/
+fn latest_version(package: &str) -> Option<String> {
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")))?;
+    let name = package.to_lowercase();
+    let prefix = match name.len() {
+        1 => PathBuf::from("1"),
+        2 => PathBuf::from("2"),
+        3 => Path::new("3").join(&name[..1]),
+        _ => Path::new(&name[..2]).join(&name[2..4]),
+    };
+
+    let mut latest: Option<(Vec<u64>, String)> = None;
+    for index in fs::read_dir(cargo_home.join("registry").join("index")).ok()? {
+        let path = index.ok()?.path().join(".cache").join(&prefix).join(&name);
+        let Ok(cache) = fs::read(&path) else {
+            continue;
+        };
+        info!("reading index cache {:?}", path);
+        for version in index_versions(&cache) {
+            if let Some(parsed) = parse_version(&version) {
+                if latest.as_ref().map_or(true, |(l, _)| parsed > *l) {
+                    latest = Some((parsed, version));
+                }
+            }
+        }
+    }
+    latest.map(|(_, version)| version)
+}
+
+/**
+Extracts the versions which are neither yanked nor pre-releases from a file in the index cache cargo keeps.
+
+The file is a header followed by pairs of versions and JSON index entries, all separated by NUL bytes.
+*/
+fn index_versions(cache: &[u8]) -> Vec<String> {
+    let re_vers: Regex = Regex::new(r#""vers"\s*:\s*"([^"]+)""#).unwrap();
+    let re_yanked: Regex = Regex::new(r#""yanked"\s*:\s*true"#).unwrap();
+    cache
+        .split(|b| *b == 0)
+        .filter(|entry| entry.starts_with(b"{"))
+        .filter_map(|entry| std::str::from_utf8(entry).ok())
+        .filter(|entry| !re_yanked.is_match(entry))
+        .filter_map(|entry| re_vers.captures(entry).map(|cap| cap[1].to_string()))
+        .filter(|version| !version.contains('-'))
+        .collect()
+}
+
+#[test]
+fn test_apply_edit() {
+    let cwd = Path::new("/cwd");
+    macro_rules! ae {
+        ($s:expr, add $dep:expr) => {
+            apply_edit($s, &DepEdit::Set(DepSpec::parse($dep, cwd).unwrap()))
+                .map_err(|e| e.to_string())
+        };
+        ($s:expr, remove $name:expr) => {
+            apply_edit($s, &DepEdit::Remove($name.into())).map_err(|e| e.to_string())
+        };
+        ($s:expr, set $name:expr, $version:expr) => {
+            ae!($s, set $name, $version, in "dependencies")
+        };
+        ($s:expr, set $name:expr, $version:expr, in $table:expr) => {
+            apply_edit($s, &DepEdit::SetVersion($table, $name.into(), $version.into()))
+                .map_err(|e| e.to_string())
+        };
+    }
+    macro_rules! ok {
+        ($s:expr) => {
+            Ok($s.to_string())
+        };
+    }
+
+    // Short comment manifests.
+    let script = "#!/usr/bin/env rust-script\n// cargo-deps: time=\"0.1.25\", libc = \"0.2.5\"\n// cargo-deps: serde(derive)=\"1\"\nfn main() {}\n";
+    assert_eq!(
+        ae!(script, add "regex@1[unicode]"),
+        ok!("#!/usr/bin/env rust-script\n// cargo-deps: time=\"0.1.25\", libc = \"0.2.5\"\n// cargo-deps: serde(derive)=\"1\", regex(unicode)=\"1\"\nfn main() {}\n")
+    );
+    assert_eq!(
+        ae!(script, add "time@0.3"),
+        ok!("#!/usr/bin/env rust-script\n// cargo-deps: time=\"0.3\", libc = \"0.2.5\"\n// cargo-deps: serde(derive)=\"1\"\nfn main() {}\n")
+    );
+    assert_eq!(
+        ae!(script, remove "time"),
+        ok!("#!/usr/bin/env rust-script\n// cargo-deps: libc = \"0.2.5\"\n// cargo-deps: serde(derive)=\"1\"\nfn main() {}\n")
+    );
+    assert_eq!(
+        ae!(script, remove "libc"),
+        ok!("#!/usr/bin/env rust-script\n// cargo-deps: time=\"0.1.25\"\n// cargo-deps: serde(derive)=\"1\"\nfn main() {}\n")
+    );
+    assert_eq!(
+        ae!(script, remove "serde"),
+        ok!("#!/usr/bin/env rust-script\n// cargo-deps: time=\"0.1.25\", libc = \"0.2.5\"\nfn main() {}\n")
+    );
+    assert_eq!(
+        ae!(script, set "serde", "1.2"),
+        ok!("#!/usr/bin/env rust-script\n// cargo-deps: time=\"0.1.25\", libc = \"0.2.5\"\n// cargo-deps: serde(derive)=\"1.2\"\nfn main() {}\n")
+    );
+    assert_eq!(
+        ae!("// cargo-deps: time, libc={ version = \"0.2\" }\n", set "time", "0.3"),
+        ok!("// cargo-deps: time=\"0.3\", libc={ version = \"0.2\" }\n")
+    );
+    assert_eq!(
+        ae!("// cargo-deps: time, libc={ version = \"0.2\" }\n", set "libc", "0.3"),
+        ok!("// cargo-deps: time, libc={ version = \"0.3\" }\n")
+    );
+    assert_eq!(
+        ae!(script, remove "regex"),
+        Err("dependency `regex` not found in the embedded manifest".into())
+    );
+
+    // Scripts without a manifest get a short comment one.
+    assert_eq!(
+        ae!("#!/usr/bin/env rust-script\nfn main() {}\n", add "mylib[fancy]:path=/cwd/mylib:no-default-features"),
+        ok!("#!/usr/bin/env rust-script\n// cargo-deps: mylib(fancy, path=\"/cwd/mylib\", default-features=false)\nfn main() {}\n")
+    );
+
+    // Code block manifests.
+    let script = r#"#!/usr/bin/env rust-script
+//! Prints the time.
+//!
+//! ```cargo
+//! [dependencies]
+//! # The good old version.
+//! time = "0.1.25"
+//! ```
+fn main() {}
+"#;
+    assert_eq!(
+        ae!(script, add "serde@1[derive]"),
+        ok!(r#"#!/usr/bin/env rust-script
+//! Prints the time.
+//!
+//! ```cargo
+//! [dependencies]
+//! # The good old version.
+//! time = "0.1.25"
+//! serde = { version = "1", features = ["derive"] }
+//! ```
+fn main() {}
+"#)
+    );
+    assert_eq!(
+        ae!(script, set "time", "0.3"),
+        ok!(r#"#!/usr/bin/env rust-script
+//! Prints the time.
+//!
+//! ```cargo
+//! [dependencies]
+//! # The good old version.
+//! time = "0.3"
+//! ```
+fn main() {}
+"#)
+    );
+    assert_eq!(
+        ae!("/*!\n    ```cargo\n    [dependencies]\n    time = \"0.1.25\"\n    ```\n*/\n", remove "time"),
+        ok!("/*!\n    ```cargo\n    [dependencies]\n    ```\n*/\n")
+    );
+
+    // Frontmatter.
+    assert_eq!(
+        ae!("---\n[dependencies]\ntime = { version = \"0.1.25\" } # Old.\n---\nfn main() {}\n", set "time", "0.3"),
+        ok!("---\n[dependencies]\ntime = { version = \"0.3\" } # Old.\n---\nfn main() {}\n")
+    );
+    assert_eq!(
+        ae!("---\n[dependencies]\ntime = \"0.1\"\n[dev-dependencies]\ntime = \"0.1\"\n---\n", set "time", "0.3", in "dev-dependencies"),
+        ok!("---\n[dependencies]\ntime = \"0.1\"\n[dev-dependencies]\ntime = \"0.3\"\n---\n")
+    );
+    assert_eq!(
+        ae!("---\n[dependencies]\n---\n", add "time@1"),
+        ok!("---\n[dependencies]\ntime = \"1\"\n---\n")
+    );
+    assert_eq!(
+        ae!("---\n[package]\nedition = \"2021\"\n---\n", add "time"),
+        ok!("---\n[package]\nedition = \"2021\"\n\n[dependencies]\ntime = \"*\"\n---\n")
+    );
+}
+
+#[test]
+fn test_registry_dependencies() {
+    let script = "---\n[dependencies]\ntime = \"0.1\"\nlocal = { path = \"local\" }\n[dev-dependencies]\nmy-serde = { package = \"serde\", version = \"1\" }\n[build-dependencies]\ncc = \"1\"\n---\n";
+    assert_eq!(
+        registry_dependencies(script, Path::new("script.rs")).unwrap(),
+        [
+            ("dependencies", "time".into(), "time".into(), "0.1".into()),
+            (
+                "dev-dependencies",
+                "my-serde".into(),
+                "serde".into(),
+                "1".into()
+            ),
+            ("build-dependencies", "cc".into(), "cc".into(), "1".into()),
+        ]
+    );
+}
+
+#[test]
+fn test_raise_requirement() {
+    assert_eq!(raise_requirement("0.1.25", "0.3.41"), Some("0.3.41".into()));
+    // Raised even though `^1.2` already allows `1.5.0`.
+    assert_eq!(raise_requirement("^1.2", "1.5.0"), Some("^1.5".into()));
+    assert_eq!(raise_requirement("1.2", "1.5.0"), Some("1.5".into()));
+    assert_eq!(raise_requirement("1.2", "1.2.9"), None);
+    assert_eq!(raise_requirement("1.5", "1.2.0"), None);
+    assert_eq!(raise_requirement("~0.2", "0.2.9"), None);
+    assert_eq!(raise_requirement("1", "1.0.219"), None);
+    assert_eq!(raise_requirement("1", "2.0.0"), Some("2".into()));
+    assert_eq!(raise_requirement("=1.0.0", "1.0.1"), Some("=1.0.1".into()));
+    assert_eq!(raise_requirement(">=1, <2", "2.0.0"), None);
+    assert_eq!(raise_requirement("*", "2.0.0"), None);
+}
+
+#[test]
+fn test_index_versions() {
+    let cache = b"\x03\x02\x00\x00\x00etag\x001.0.0\x00{\"name\": \"a\", \"vers\": \"1.0.0\", \"yanked\": false}\x001.1.0\x00{\"name\": \"a\", \"vers\": \"1.1.0\", \"yanked\": true}\x002.0.0-rc.1\x00{\"name\": \"a\", \"vers\": \"2.0.0-rc.1\", \"yanked\": false}\x00";
+    assert_eq!(index_versions(cache), vec!["1.0.0".to_string()]);
+}
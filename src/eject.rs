@@ -0,0 +1,137 @@
+/*!
+This module is concerned with turning a script into a standalone Cargo project.
+*/
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use log::info;
+
+use crate::consts;
+use crate::error::{MainError, MainResult};
+use crate::manifest;
+use crate::templates;
+use crate::{Input, InputAction};
+
+/**
+Writes the script as a regular Cargo project into `dir`, which must not exist yet or be empty.
+
+Unlike the generated package, the project doesn't depend on `rust-script` or the location of the script: it has a `src/main.rs` without the embedded manifest, a manifest with a readable name and relative paths, a `build.rs` if the script embeds one, and the lockfile the script was last built with, if any.
+*/
+pub fn eject(input: &Input, action: &InputAction, dir: &Path) -> MainResult<()> {
+    let dir = std::env::current_dir()?.join(dir);
+    if fs::read_dir(&dir).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(format!(
+            "cannot eject into {}: the directory is not empty",
+            dir.display()
+        )
+        .into());
+    }
+
+    if !action.libraries.is_empty() {
+        return Err("cannot eject a script which depends on library scripts".into());
+    }
+
+    let source = match input {
+        Input::File(_, path, _, _) if manifest::is_markdown(path) => {
+            let script = action
+                .script
+                .as_deref()
+                .expect("Markdown scripts always have their source generated");
+            // The blank lines standing in for the prose only matter for compiler messages.
+            let mut source = String::new();
+            for line in script.trim_start().lines() {
+                if !(line.trim().is_empty() && (source.is_empty() || source.ends_with("\n\n"))) {
+                    source.push_str(line);
+                    source.push('\n');
+                }
+            }
+            source
+        }
+        Input::File(_, _, content, _) | Input::Stdin(content, _) => {
+            let source = manifest::strip_embedded_manifest(content);
+            if manifest::contains_main_method(&source) {
+                source
+            } else {
+                let mut subs = HashMap::with_capacity(1);
+                subs.insert(consts::SCRIPT_BODY_SUB, &source[..]);
+                templates::expand(consts::FILE_NO_MAIN_TEMPLATE, &subs)?
+                    .trim_start()
+                    .into()
+            }
+        }
+        Input::Expr(..) | Input::Loop(..) | Input::Repl(..) => action
+            .script
+            .clone()
+            .expect("expressions and loops always have their source generated")
+            .trim_start()
+            .into(),
+    };
+
+    let package_name = input.package_name();
+    let mut mani: toml::value::Table =
+        toml::from_str(&action.manifest).map_err(|e| MainError::Other(Box::new(e)))?;
+
+    let mut toolchain = None;
+    if let Some(toml::Value::Table(package)) = mani.get_mut("package") {
+        package.insert("name".into(), package_name.clone().into());
+        package.remove("authors");
+        // Cargo finds `build.rs` on its own too.
+        if action.build_script.is_some() {
+            package.remove("build");
+        }
+
+        if let Some(toml::Value::Table(metadata)) = package.get_mut("metadata") {
+            // Settings for rust-script mean nothing to cargo, except for the toolchain, which has a file of its own.
+            if let Some(settings) = metadata.remove("rustscript") {
+                toolchain = settings
+                    .get("toolchain")
+                    .and_then(|t| t.as_str())
+                    .map(String::from);
+            }
+            if metadata.is_empty() {
+                package.remove("metadata");
+            }
+        }
+    }
+    if let Some(toml::Value::Array(bins)) = mani.get_mut("bin") {
+        // The script itself becomes `src/main.rs`, which cargo finds on its own.
+        bins.retain(|bin| bin.get("name").and_then(|n| n.as_str()) != Some(&action.bin_name));
+        if bins.is_empty() {
+            mani.remove("bin");
+        }
+    }
+    let mani = manifest::unfix_manifest_paths(mani, &dir)?;
+
+    // Prefer the script's own lockfile, since the one in the cache may have been cleaned up.
+    let lockfile = action
+        .lockfile_path
+        .iter()
+        .chain(Some(&action.package_lockfile_path()))
+        .find_map(|path| fs::read_to_string(path).ok())
+        .map(|lockfile| {
+            lockfile.replace(
+                &format!("name = \"{}\"", action.bin_name),
+                &format!("name = \"{}\"", package_name),
+            )
+        });
+
+    info!("ejecting into {:?}", dir);
+    fs::create_dir_all(dir.join("src"))?;
+    fs::write(dir.join("src").join("main.rs"), source)?;
+    fs::write(dir.join("Cargo.toml"), manifest::format_manifest(mani))?;
+    if let Some(build_script) = &action.build_script {
+        fs::write(dir.join("build.rs"), build_script)?;
+    }
+    if let Some(lockfile) = lockfile {
+        fs::write(dir.join("Cargo.lock"), lockfile)?;
+    }
+    if let Some(toolchain) = toolchain {
+        fs::write(
+            dir.join("rust-toolchain.toml"),
+            format!("[toolchain]\nchannel = {}\n", toml::Value::from(toolchain)),
+        )?;
+    }
+
+    Ok(())
+}
@@ -50,6 +50,7 @@ macro_rules! from_impl {
 from_impl! { io::Error => MainError, v -> MainError::Io(v) }
 from_impl! { String => MainError, v -> MainError::OtherOwned(v) }
 from_impl! { &'static str => MainError, v -> MainError::OtherBorrowed(v) }
+from_impl! { rustyline::error::ReadlineError => MainError, v -> MainError::Other(Box::new(v)) }
 
 impl<T> From<Box<T>> for MainError
 where
@@ -0,0 +1,253 @@
+/*!
+This module is concerned with scripts used as libraries by other scripts, through dependencies like `common = { script = "common.rs" }`.
+
+Each library script gets a library package of its own in the cache, and the dependency is turned into a path dependency on that package.
+*/
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::info;
+use sha1::{Digest, Sha1};
+
+use crate::consts;
+use crate::dependency::WildcardPolicy;
+use crate::error::{MainError, MainResult};
+use crate::manifest::{self, Manifest};
+use crate::platform;
+use crate::Input;
+
+/// Name of the library source in the package, when the script can't be compiled in place.
+const LIB_SOURCE: &str = "lib.rs";
+
+/**
+A library package generated for a library script.
+*/
+#[derive(Debug)]
+pub struct Library {
+    /// Path of the library script.
+    pub script_path: PathBuf,
+
+    /// Name of the package.
+    pub name: String,
+
+    /// Directory where the package should live.
+    pub pkg_path: PathBuf,
+
+    /// The package manifest contents.
+    pub manifest: String,
+
+    /// The library source in case it has to be written.
+    pub source: Option<String>,
+}
+
+impl Library {
+    /// Writes the package, leaving alone any files which haven't changed.
+    pub fn generate(&self) -> MainResult<()> {
+        info!("generating library package {:?}", self.pkg_path);
+        fs::create_dir_all(&self.pkg_path)?;
+        crate::overwrite_file(&self.pkg_path.join("Cargo.toml"), &self.manifest)?;
+        if let Some(source) = &self.source {
+            crate::overwrite_file(&self.pkg_path.join(LIB_SOURCE), source)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+Replaces each `script` dependency in the manifest with a path dependency on the package of the library script, and returns the packages which have to be generated.
+
+These include the packages for the script dependencies of the library scripts themselves.  `dependent` is the script the manifest belongs to, if any, so that it isn't used as a library of its own.
+
+The `script` paths must already be absolute.
+*/
+pub fn resolve_script_dependencies(
+    mani: &mut toml::value::Table,
+    dependent: Option<&Path>,
+    wildcard_policy: WildcardPolicy,
+) -> MainResult<Vec<Library>> {
+    let mut libraries = Vec::new();
+    let mut chain: Vec<PathBuf> = dependent.map(PathBuf::from).into_iter().collect();
+    resolve(mani, &mut chain, &mut libraries, wildcard_policy)?;
+    Ok(libraries)
+}
+
+fn resolve(
+    mani: &mut toml::value::Table,
+    chain: &mut Vec<PathBuf>,
+    libraries: &mut Vec<Library>,
+    wildcard_policy: WildcardPolicy,
+) -> MainResult<()> {
+    for (name, dep) in script_dependencies(mani) {
+        let script_path = match dep.remove("script") {
+            Some(toml::Value::String(path)) => PathBuf::from(path),
+            _ => {
+                return Err(format!("the `script` of dependency `{}` must be a path", name).into())
+            }
+        };
+
+        if let Some(start) = chain.iter().position(|path| *path == script_path) {
+            let cycle: Vec<String> = chain[start..]
+                .iter()
+                .chain(Some(&script_path))
+                .map(|path| path.display().to_string())
+                .collect();
+            return Err(format!("scripts depend on each other: {}", cycle.join(" -> ")).into());
+        }
+
+        let index = match libraries.iter().position(|l| l.script_path == script_path) {
+            Some(index) => index,
+            None => {
+                chain.push(script_path.clone());
+                let library = library(&script_path, chain, libraries, wildcard_policy)?;
+                chain.pop();
+                libraries.push(library);
+                libraries.len() - 1
+            }
+        };
+        let library = &libraries[index];
+
+        let pkg_path = library
+            .pkg_path
+            .to_str()
+            .ok_or_else(|| format!("Unable to stringify {:?}", library.pkg_path))?;
+        dep.insert("path".into(), pkg_path.into());
+        dep.insert("package".into(), library.name.clone().into());
+    }
+    Ok(())
+}
+
+/**
+Builds the package for the library script at `script_path`, resolving its own script dependencies along the way.
+*/
+fn library(
+    script_path: &Path,
+    chain: &mut Vec<PathBuf>,
+    libraries: &mut Vec<Library>,
+    wildcard_policy: WildcardPolicy,
+) -> MainResult<Library> {
+    let content = fs::read_to_string(script_path).map_err(|e| {
+        MainError::Tag(
+            format!("could not read library script {}", script_path.display()).into(),
+            Box::new(e.into()),
+        )
+    })?;
+    let dir = manifest::script_dir(script_path);
+    let name = script_path
+        .file_stem()
+        .map(|os| os.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "unknown".into());
+    let package_name =
+        Input::File(name, script_path.into(), String::new(), dir.into()).package_name();
+    let pkg_path = platform::generated_projects_cache_path().join(library_id(script_path));
+
+    let full_content = &content;
+    let content = manifest::strip_shebang(full_content);
+    let (part_mani, source) =
+        manifest::find_embedded_manifest(content).unwrap_or((Manifest::Toml(""), content));
+
+    // Like any crate root, the script can be compiled in place, unless there is a frontmatter to cut out.
+    let (lib_path, source) = if source.len() < content.len() {
+        let blanked = manifest::blank_manifest(full_content, source);
+        (
+            pkg_path.join(LIB_SOURCE),
+            Some(manifest::anchor_relative_paths(&blanked, dir)),
+        )
+    } else {
+        (script_path.into(), None)
+    };
+
+    let part_mani = part_mani.into_toml(full_content, &script_path.display().to_string())?;
+    let mani = manifest::merge_manifest(default_manifest(&package_name, &lib_path), part_mani)?;
+    let mut mani = manifest::fix_manifest_paths(mani, dir)?;
+    resolve(&mut mani, chain, libraries, wildcard_policy)?;
+
+    let from = format!("the library script {}", script_path.display());
+    manifest::check_unpinned_deps(&mani, |_| &from, wildcard_policy)?;
+
+    let name = mani
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(|name| name.as_str())
+        .unwrap_or_default()
+        .to_string();
+
+    Ok(Library {
+        script_path: script_path.into(),
+        name,
+        pkg_path,
+        manifest: mani.to_string(),
+        source,
+    })
+}
+
+/**
+Generates the manifest a library script starts out with, before its embedded manifest is merged in.
+*/
+fn default_manifest(name: &str, lib_path: &Path) -> toml::value::Table {
+    let mut package = toml::value::Table::new();
+    package.insert("name".into(), name.into());
+    package.insert("version".into(), "0.1.0".into());
+    package.insert("edition".into(), "2021".into());
+
+    let mut lib = toml::value::Table::new();
+    lib.insert(
+        "path".into(),
+        lib_path.to_string_lossy().into_owned().into(),
+    );
+
+    let mut mani = toml::value::Table::new();
+    mani.insert("package".into(), package.into());
+    mani.insert("lib".into(), lib.into());
+    mani
+}
+
+/**
+Computes the name of the cache folder for the package of a library script.
+
+This is kept apart from the package of the same script run on its own.
+*/
+fn library_id(script_path: &Path) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(b"library:");
+    hasher.update(&*script_path.to_string_lossy());
+    let mut digest = format!("{:x}", hasher.finalize());
+    digest.truncate(consts::ID_DIGEST_LEN_MAX);
+    digest
+}
+
+/**
+Returns the dependencies with a `script` key, along with their names, from all dependency tables including platform-specific ones.
+*/
+fn script_dependencies(mani: &mut toml::value::Table) -> Vec<(&str, &mut toml::value::Table)> {
+    const TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+    fn push_tables<'m>(
+        deps: &mut Vec<(&'m str, &'m mut toml::value::Table)>,
+        table: &'m mut toml::value::Table,
+    ) {
+        for (table_name, value) in table.iter_mut() {
+            let toml::Value::Table(table) = value else {
+                continue;
+            };
+            if table_name == "target" {
+                for (_, target) in table.iter_mut() {
+                    if let toml::Value::Table(target) = target {
+                        push_tables(deps, target);
+                    }
+                }
+            } else if TABLES.contains(&table_name.as_str()) {
+                for (name, dep) in table.iter_mut() {
+                    if let toml::Value::Table(dep) = dep {
+                        if dep.contains_key("script") {
+                            deps.push((name.as_str(), dep));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut deps = Vec::new();
+    push_tables(&mut deps, mani);
+    deps
+}
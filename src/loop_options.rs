@@ -0,0 +1,291 @@
+/*!
+This module is concerned with the options of `--loop` scripts, which decide how the generated program reads its input and calls the closure.
+*/
+use sha1::{Digest, Sha1};
+
+use std::collections::HashMap;
+
+use crate::arguments::Args;
+use crate::consts;
+use crate::error::MainResult;
+use crate::output_format::OutputFormat;
+use crate::templates;
+
+/// What the loop closure is given for each line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoopInput {
+    /// The line itself, including its line ending.
+    Line,
+    /// The fields of the line, split at a delimiter, or at runs of whitespace if it is a single space.
+    Split(String),
+    /// The captures of a regex on the line.  Lines which don't match are skipped.
+    Regex(String),
+    /// The line, parsed into the given type.
+    Parse(String),
+}
+
+/// How each line is decoded before the closure is given it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopEncoding {
+    /// The line must be valid UTF-8, or the loop stops with an error.
+    Utf8,
+    /// Invalid UTF-8 is replaced with U+FFFD, and the closure is given a `Cow<str>`.
+    Lossy,
+    /// The line is not decoded at all, and the closure is given a `&[u8]`.
+    Bytes,
+}
+
+/// How a `--loop` script is run.
+#[derive(Clone, Debug)]
+pub struct LoopOptions {
+    /// Whether the closure is also given the line number.
+    pub count: bool,
+    pub input: LoopInput,
+    pub encoding: LoopEncoding,
+    /// Whether the input is made of NUL-terminated records, rather than lines.
+    pub null: bool,
+    /// Code run before the first line, whose value is the state the closure is given.
+    pub begin: Option<String>,
+    /// Code run after the last line, which can use the state.
+    pub end: Option<String>,
+    /// How the values of the closure and of `end` are printed.
+    pub format: OutputFormat,
+    /// The number of threads to run the closure on, or `0` for one per CPU, if it is run in parallel.
+    pub parallel: Option<usize>,
+}
+
+impl LoopOptions {
+    pub fn from_args(args: &Args) -> MainResult<Self> {
+        let input = if let Some(delimiter) = &args.split {
+            LoopInput::Split(delimiter.clone())
+        } else if let Some(pattern) = &args.regex {
+            // Better to find out now than from the generated program.
+            regex::Regex::new(pattern).map_err(|e| format!("invalid `--regex` pattern: {}", e))?;
+            LoopInput::Regex(pattern.clone())
+        } else if let Some(ty) = &args.parse {
+            LoopInput::Parse(ty.clone())
+        } else {
+            LoopInput::Line
+        };
+        let encoding = if args.bytes {
+            LoopEncoding::Bytes
+        } else if args.lossy {
+            LoopEncoding::Lossy
+        } else {
+            LoopEncoding::Utf8
+        };
+        Ok(LoopOptions {
+            count: args.count,
+            input,
+            encoding,
+            null: args.null,
+            begin: args.begin.clone(),
+            end: args.end.clone(),
+            format: args.format,
+            parallel: args.parallel,
+        })
+    }
+
+    /**
+    Adds the options to the hash the package ID is made from, since they change the generated program.
+    */
+    pub fn hash(&self, hasher: &mut Sha1) {
+        hasher.update("count:");
+        hasher.update(if self.count { "true;" } else { "false;" });
+
+        let input = match &self.input {
+            LoopInput::Line => None,
+            LoopInput::Split(delimiter) => Some(("split", delimiter.as_str())),
+            LoopInput::Regex(pattern) => Some(("regex", pattern.as_str())),
+            LoopInput::Parse(ty) => Some(("parse", ty.as_str())),
+        };
+        let encoding = match self.encoding {
+            LoopEncoding::Utf8 => None,
+            LoopEncoding::Lossy => Some(("encoding", "lossy")),
+            LoopEncoding::Bytes => Some(("encoding", "bytes")),
+        };
+        let parts = input
+            .into_iter()
+            .chain(encoding)
+            .chain(Some(("separator", "nul")).filter(|_| self.null))
+            .chain(self.begin.as_deref().map(|code| ("begin", code)))
+            .chain(self.end.as_deref().map(|code| ("end", code)));
+        let threads = self.parallel.map(|threads| threads.to_string());
+        let parts = parts.chain(threads.as_deref().map(|threads| ("parallel", threads)));
+        for (name, value) in parts {
+            hasher.update(name);
+            hasher.update(":");
+            hasher.update(value.len().to_string());
+            hasher.update(":");
+            hasher.update(value);
+            hasher.update(";");
+        }
+        self.format.hash(hasher);
+    }
+
+    /**
+    Returns the template for these options, either `LOOP_TEMPLATE` or `PARALLEL_LOOP_TEMPLATE`.
+    */
+    pub fn template(&self) -> &'static str {
+        if self.parallel.is_some() {
+            consts::PARALLEL_LOOP_TEMPLATE
+        } else {
+            consts::LOOP_TEMPLATE
+        }
+    }
+
+    /**
+    Returns the substitutions for the template, which turn it into the variant for these options.
+    */
+    pub fn template_subs(&self) -> MainResult<Vec<(&'static str, String)>> {
+        // Stops the loop with an error.  In parallel, the error is handed back in place of the line's result instead, so that it still comes after the output of the lines before it.
+        let fail = |message: String| {
+            if self.parallel.is_some() {
+                format!(
+                    "results.push((count, Err((format!({}), 1))));\n                break;",
+                    message
+                )
+            } else {
+                format!(
+                    "eprintln!({});\n                std::process::exit(1);",
+                    message
+                )
+            }
+        };
+
+        // Lines keep their line ending, as they always have, but a NUL is of no use to anyone.
+        let (separator, record, what) = if self.null {
+            (
+                "b'\\0'",
+                "let record = buffer.strip_suffix(b\"\\0\").unwrap_or(&buffer);",
+                "record",
+            )
+        } else {
+            ("b'\\n'", "let record = &buffer[..];", "line")
+        };
+        let decode = match self.encoding {
+            LoopEncoding::Utf8 => format!(
+                "let line_buffer = match std::str::from_utf8(record) {{\n            Ok(line) => line,\n            Err(e) => {{\n                {}\n            }}\n        }};",
+                fail(format!(
+                    "\"error: {} {{}} is not valid UTF-8 ({{}}); use `--lossy` or `--bytes` to read it anyway\", count, e",
+                    what
+                ))
+            ),
+            LoopEncoding::Lossy => "let line_buffer = String::from_utf8_lossy(record);".into(),
+            LoopEncoding::Bytes => "let line_buffer = record;".into(),
+        };
+
+        // The other kinds of input are made from the line without its line ending.
+        let line = if self.null {
+            "let line: &str = &line_buffer;\n        "
+        } else {
+            "let line = line_buffer.trim_end_matches(&['\\n', '\\r'][..]);\n        "
+        };
+        let (setup, input, input_type) = match &self.input {
+            LoopInput::Line => (
+                String::new(),
+                "let input = line_buffer;".to_string(),
+                match self.encoding {
+                    LoopEncoding::Utf8 => "&str",
+                    LoopEncoding::Lossy => "std::borrow::Cow<str>",
+                    LoopEncoding::Bytes => "&[u8]",
+                }
+                .to_string(),
+            ),
+            LoopInput::Split(delimiter) => {
+                let split = if delimiter == " " {
+                    "line.split_whitespace()".to_string()
+                } else {
+                    format!("line.split({:?})", delimiter)
+                };
+                (
+                    String::new(),
+                    format!(
+                        "{}let fields: Vec<&str> = {}.collect();\n        let input = &fields[..];",
+                        line, split
+                    ),
+                    "&[&str]".to_string(),
+                )
+            }
+            LoopInput::Regex(pattern) => (
+                // A reference, which threads of a parallel loop can share.
+                format!("let regex = &regex::Regex::new({:?}).unwrap();", pattern),
+                format!(
+                    "{}let captures = match regex.captures(line) {{\n            Some(captures) => captures,\n            None => continue,\n        }};\n        let input = &captures;",
+                    line
+                ),
+                "&regex::Captures".to_string(),
+            ),
+            LoopInput::Parse(ty) => (
+                String::new(),
+                format!(
+                    "{}let input: {} = match line.parse() {{\n            Ok(value) => value,\n            Err(e) => {{\n                {}\n            }}\n        }};",
+                    line,
+                    ty,
+                    fail(format!(
+                        "\"error: could not parse {} {{}} ({{:?}}): {{}}\", count, line, e",
+                        what
+                    ))
+                ),
+                ty.clone(),
+            ),
+        };
+
+        let (count_param, count_arg) = if self.count {
+            (", usize", ", count")
+        } else {
+            ("", "")
+        };
+        // Only with `--begin` is there any state worth handing to the closure.
+        let (state_param, state_arg) = if self.begin.is_some() {
+            ("&mut S, ", "&mut state, ")
+        } else {
+            ("", "")
+        };
+
+        let mut subs: Vec<(&'static str, String)> = vec![
+            (
+                "loop_begin",
+                self.begin.clone().unwrap_or_else(|| "()".into()),
+            ),
+            ("loop_end", self.end.clone().unwrap_or_else(|| "()".into())),
+            ("loop_state_param", state_param.into()),
+            ("loop_state_arg", state_arg.into()),
+            ("loop_separator", separator.into()),
+            ("loop_record", format!("{}\n        {}", record, decode)),
+            ("loop_setup", setup),
+            ("loop_input", input),
+            ("loop_input_type", input_type),
+            ("loop_count_param", count_param.into()),
+            ("loop_count_arg", count_arg.into()),
+            ("loop_what", what.into()),
+            (
+                "loop_threads",
+                self.parallel.unwrap_or_default().to_string(),
+            ),
+        ];
+        subs.extend(self.format.template_subs());
+
+        // The output items go in both templates, so they have substitutions of their own.
+        let loop_output = {
+            let subs: HashMap<&str, &str> = subs
+                .iter()
+                .map(|(name, value)| (*name, value.as_str()))
+                .collect();
+            templates::expand(consts::LOOP_OUTPUT_ITEMS, &subs)?
+        };
+        subs.push(("loop_output", loop_output));
+        Ok(subs)
+    }
+
+    /**
+    Returns the dependencies the generated program needs, in the same form as those given with `--dep`.
+    */
+    pub fn dependencies(&self) -> Vec<(String, String)> {
+        let mut deps = self.format.dependencies();
+        if let LoopInput::Regex(_) = self.input {
+            deps.push(("regex".into(), "1".into()));
+        }
+        deps
+    }
+}
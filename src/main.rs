@@ -2,11 +2,21 @@
 
 mod arguments;
 mod build_kind;
+mod config;
 mod consts;
 mod defer;
+mod dependency;
+mod edit;
+mod eject;
 mod error;
+mod library;
+mod loop_options;
 mod manifest;
+mod output_format;
+mod pack;
 mod platform;
+mod remote;
+mod repl;
 mod templates;
 
 #[cfg(windows)]
@@ -24,7 +34,7 @@ use std::ffi::OsString;
 use std::fs;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 
 use crate::build_kind::BuildKind;
 use crate::defer::Defer;
@@ -62,43 +72,43 @@ fn try_main() -> MainResult<i32> {
 
     if args.clear_cache {
         clean_cache(0)?;
-        if args.script.is_none() {
+        if args.script.is_none() && !args.repl {
             println!("rust-script cache cleared.");
             return Ok(0);
         }
     }
 
+    if let Some(edit) = &args.edit {
+        edit::edit_script(edit.command, &edit.script, &edit.deps)?;
+        return Ok(0);
+    }
+
+    if let Some(project_dir) = &args.pack {
+        let script_path = pack::pack(
+            Path::new(project_dir),
+            args.pack_output.as_deref().map(Path::new),
+        )?;
+        println!("{}", script_path.display());
+        return Ok(0);
+    }
+
+    let config = config::Config::load()?;
+
     // Sort out the dependencies.  We want to do a few things:
     // - Sort them so that they hash consistently.
     // - Check for duplicates.
-    // - Expand `pkg` into `pkg=*`.
+    // - Turn each specification into the value of its manifest entry, expanding `pkg` into `pkg=*`.
     let dependencies_from_args = {
         use std::collections::HashMap;
 
+        let cwd = std::env::current_dir()?;
         let mut deps: HashMap<String, String> = HashMap::new();
-        for dep in args.dep.iter().cloned() {
-            // Append '=*' if it needs it.
-            let dep = match dep.find('=') {
-                Some(_) => dep,
-                None => dep + "=*",
-            };
-
-            let mut parts = dep.splitn(2, '=');
-            let name = parts.next().expect("dependency is missing name");
-            let version = parts.next().expect("dependency is missing version");
-            assert!(
-                parts.next().is_none(),
-                "dependency somehow has three parts?!"
-            );
-
-            if name.is_empty() {
-                return Err(("cannot have empty dependency package name").into());
-            } else if version.is_empty() {
-                return Err(("cannot have empty dependency version").into());
-            }
+        for dep in &args.dep {
+            let dep = dependency::DepSpec::parse(dep, &cwd)?;
+            let value = dep.to_manifest_value();
 
-            if deps.insert(name.into(), version.into()).is_some() {
-                return Err((format!("duplicated dependency: '{}'", name)).into());
+            if deps.insert(dep.name.clone(), value).is_some() {
+                return Err((format!("duplicated dependency: '{}'", dep.name)).into());
             }
         }
 
@@ -108,10 +118,41 @@ fn try_main() -> MainResult<i32> {
         deps
     };
 
+    let wildcard_policy = args
+        .wildcard_deps
+        .or(config.wildcard_deps)
+        .unwrap_or_default();
+
+    if args.repl {
+        return repl::run(&args, dependencies_from_args, wildcard_policy);
+    }
+
+    let mut script_url = None;
     let input = match (args.script.clone().unwrap(), args.expr, args.loop_) {
+        (_, false, false) if args.stdin => {
+            let base_path = if let Some(base_path_arg) = &args.base_path {
+                Path::new(base_path_arg).into()
+            } else {
+                std::env::current_dir().expect("couldn't get current directory for input base path")
+            };
+            Input::Stdin(read_stdin_script()?, base_path)
+        }
         (script, false, false) => {
+            let script = if remote::is_url(&script) {
+                let sha256 = args.sha256.as_ref().or(config.sha256.get(&script));
+                let path = remote::fetch_script(&script, sha256.map(String::as_str))?;
+                script_url = Some(script);
+                path.to_string_lossy().into_owned()
+            } else if args.sha256.is_some() {
+                return Err("`--sha256` can only be used for a script given as a URL".into());
+            } else {
+                script
+            };
             let (path, mut file) =
                 find_script(script.as_ref()).ok_or(format!("could not find script: {}", script))?;
+            if args.block.is_some() && !manifest::is_markdown(&path) {
+                return Err("`--block` can only be used for a Markdown script".into());
+            }
 
             let script_name = path
                 .file_stem()
@@ -140,7 +181,13 @@ fn try_main() -> MainResult<i32> {
             } else {
                 std::env::current_dir().expect("couldn't get current directory for input base path")
             };
-            Input::Expr(expr, base_path)
+            // The type is printed by the expression itself, which then has no value to print.
+            let expr = if args.type_ {
+                repl::type_of(&expr)
+            } else {
+                expr
+            };
+            Input::Expr(expr, args.format, base_path)
         }
         (loop_, false, true) => {
             let base_path = if let Some(base_path_arg) = &args.base_path {
@@ -148,7 +195,11 @@ fn try_main() -> MainResult<i32> {
             } else {
                 std::env::current_dir().expect("couldn't get current directory for input base path")
             };
-            Input::Loop(loop_, args.count, base_path)
+            Input::Loop(
+                loop_,
+                loop_options::LoopOptions::from_args(&args)?,
+                base_path,
+            )
         }
         (_, _, _) => {
             panic!("Internal error: Invalid args");
@@ -165,6 +216,7 @@ fn try_main() -> MainResult<i32> {
     std::env::set_var("RUST_SCRIPT_SAFE_NAME", input.safe_name());
     std::env::set_var("RUST_SCRIPT_PKG_NAME", input.package_name());
     std::env::set_var("RUST_SCRIPT_BASE_PATH", input.base_path());
+    std::env::set_var("RUST_SCRIPT_URL", script_url.as_deref().unwrap_or(""));
 
     // Generate the prelude items, if we need any. Ensure consistent and *valid* sorting.
     let prelude_items = {
@@ -184,9 +236,31 @@ fn try_main() -> MainResult<i32> {
     };
     info!("prelude_items: {:?}", prelude_items);
 
-    let action = decide_action_for(&input, dependencies_from_args, prelude_items, &args)?;
+    // Some loop options and output formats need crates of their own, unless the user already asked for them.
+    let mut deps = dependencies_from_args;
+    let input_deps = match &input {
+        Input::Expr(_, format, _) => format.dependencies(),
+        Input::Loop(_, options, _) => options.dependencies(),
+        _ => Vec::new(),
+    };
+    if !input_deps.is_empty() {
+        for (name, value) in input_deps {
+            if !deps.iter().any(|(dep, _)| *dep == name) {
+                deps.push((name, value));
+            }
+        }
+        deps.sort();
+    }
+
+    let action = decide_action_for(&input, deps, prelude_items, &args, wildcard_policy)?;
     info!("action: {:?}", action);
 
+    if let Some(dir) = &args.eject {
+        eject::eject(&input, &action, Path::new(dir))?;
+        println!("{}", dir);
+        return Ok(0);
+    }
+
     generate_package(&action)?;
 
     // Once we're done, clean out old packages from the cache.
@@ -206,7 +280,12 @@ fn try_main() -> MainResult<i32> {
         return Ok(0);
     }
 
-    let mut cmd = action.command_to_execute(&args.script_args, args.wrapper)?;
+    if args.fetch {
+        action.fetch_dependencies()?;
+        return Ok(0);
+    }
+
+    let mut cmd = action.command_to_execute()?;
     #[cfg(unix)]
     {
         let err = cmd.exec();
@@ -233,6 +312,11 @@ fn clean_cache(max_age: u128) -> MainResult<()> {
         if let Err(err) = fs::remove_dir_all(&cache_dir) {
             error!("failed to remove binary cache {:?}: {}", cache_dir, err);
         }
+
+        let cache_dir = platform::fetched_scripts_cache_path();
+        if let Err(err) = fs::remove_dir_all(&cache_dir) {
+            error!("failed to remove fetched scripts {:?}: {}", cache_dir, err);
+        }
     }
 
     let cutoff = platform::current_time() - max_age;
@@ -292,6 +376,28 @@ fn generate_package(action: &InputAction) -> MainResult<()> {
         overwrite_file(&action.script_path, script)?;
     }
 
+    for library in &action.libraries {
+        library.generate()?;
+    }
+
+    // Cargo would also pick up a `build.rs` left behind by an earlier version of the script.
+    match &action.build_script {
+        Some(build_script) => overwrite_file(&action.build_script_path(), build_script)?,
+        None => match fs::remove_file(action.build_script_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(MainError::Io(e)),
+            _ => {}
+        },
+    }
+
+    // Cargo picks up a lockfile from the package, so seed it with the script's own.
+    if let Some(lockfile_path) = &action.lockfile_path {
+        match fs::read_to_string(lockfile_path) {
+            Ok(lockfile) => overwrite_file(&action.package_lockfile_path(), &lockfile)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(MainError::Io(e)),
+        }
+    }
+
     info!("disarming pkg dir cleanup...");
     cleanup_dir.disarm();
 
@@ -345,9 +451,39 @@ struct InputAction {
     /// The script source in case it has to be written.
     script: Option<String>,
 
+    /// The build script embedded in the script, if any.
+    build_script: Option<String>,
+
+    /// Packages for the library scripts the script depends on.
+    libraries: Vec<library::Library>,
+
     /// Did the user ask to run tests or benchmarks?
     build_kind: BuildKind,
 
+    /// Cargo features to enable.
+    features: Vec<String>,
+
+    /// Wrapper to run the built binary with, if any.
+    wrapper: Option<String>,
+
+    /// Arguments to pass to the script (or to `cargo test` and `cargo bench`).
+    script_args: Vec<String>,
+
+    /// Environment variables to set for the script, unless they are already set.
+    env: Vec<(String, String)>,
+
+    /// The lockfile kept next to the script, if one is used.
+    lockfile_path: Option<PathBuf>,
+
+    /// Pass `--locked` to cargo?
+    locked: bool,
+
+    /// Pass `--frozen` to cargo?
+    frozen: bool,
+
+    /// Pass `--offline` to cargo?
+    offline: bool,
+
     // Name of the built binary
     bin_name: String,
 
@@ -361,14 +497,52 @@ impl InputAction {
         self.pkg_path.join("Cargo.toml")
     }
 
-    fn command_to_execute(
-        &self,
-        script_args: &[String],
-        wrapper: Option<String>,
-    ) -> MainResult<Command> {
+    fn package_lockfile_path(&self) -> PathBuf {
+        self.pkg_path.join("Cargo.lock")
+    }
+
+    fn build_script_path(&self) -> PathBuf {
+        self.pkg_path.join("build.rs")
+    }
+
+    /**
+    Downloads the dependencies of the package with `cargo fetch`, so that the script can later be built with `--offline`.
+    */
+    fn fetch_dependencies(&self) -> MainResult<()> {
+        let mut cmd = Command::new("cargo");
+        if let Some(toolchain_version) = &self.toolchain_version {
+            cmd.arg(format!("+{}", toolchain_version));
+        }
+        cmd.arg("fetch");
+        if self.locked {
+            cmd.arg("--locked");
+        }
+        cmd.current_dir(&self.pkg_path);
+
+        if platform::force_cargo_color() {
+            cmd.arg("--color").arg("always");
+        }
+
+        if cmd.status()?.code() != Some(0) {
+            return Err(MainError::OtherBorrowed("Could not fetch dependencies"));
+        }
+        self.save_lockfile()
+    }
+
+    /// Copies the lockfile cargo resolved back next to the script.
+    fn save_lockfile(&self) -> MainResult<()> {
+        if let Some(lockfile_path) = &self.lockfile_path {
+            let lockfile = fs::read_to_string(self.package_lockfile_path())?;
+            overwrite_file(lockfile_path, &lockfile)?;
+        }
+        Ok(())
+    }
+
+    /// Returns where cargo puts the binary it builds.
+    fn built_binary_path(&self) -> PathBuf {
         let release_mode = !self.debug && !matches!(self.build_kind, BuildKind::Bench);
 
-        let built_binary_path = platform::binary_cache_path()
+        platform::binary_cache_path()
             .join(if release_mode { "release" } else { "debug" })
             .join({
                 #[cfg(windows)]
@@ -379,13 +553,21 @@ impl InputAction {
                 {
                     &self.bin_name
                 }
-            });
+            })
+    }
+
+    fn command_to_execute(&self) -> MainResult<Command> {
+        let release_mode = !self.debug && !matches!(self.build_kind, BuildKind::Bench);
+
+        let built_binary_path = self.built_binary_path();
 
         let manifest_path = self.manifest_path();
 
+        let script_args = &self.script_args;
+
         let execute_command = || {
-            if let Some(wrapper) = wrapper {
-                let wrapper_words = shell_words::split(&wrapper).unwrap();
+            let mut cmd = if let Some(wrapper) = &self.wrapper {
+                let wrapper_words = shell_words::split(wrapper).unwrap();
                 if wrapper_words.is_empty() {
                     return MainResult::Err(MainError::OtherBorrowed(
                         "The wrapper cannot be empty",
@@ -397,7 +579,7 @@ impl InputAction {
                 }
                 cmd.arg(&built_binary_path);
                 cmd.args(script_args.iter());
-                Ok(cmd)
+                cmd
             } else {
                 let mut cmd = Command::new(&built_binary_path);
                 #[cfg(unix)]
@@ -405,8 +587,14 @@ impl InputAction {
                     cmd.arg0(original_script_path);
                 }
                 cmd.args(script_args.iter());
-                Ok(cmd)
+                cmd
+            };
+            for (name, value) in &self.env {
+                if std::env::var_os(name).is_none() {
+                    cmd.env(name, value);
+                }
             }
+            Ok(cmd)
         };
 
         if matches!(self.build_kind, BuildKind::Normal) && !self.force_compile {
@@ -425,8 +613,34 @@ impl InputAction {
                         (Ok(script_file), Ok(manifest_file)) => {
                             let script_mtime = script_file.metadata()?.modified()?;
                             let manifest_mtime = manifest_file.metadata()?.modified()?;
+                            // A lockfile copied in from next to the script may pin different versions.
+                            let lockfile_mtime = match &self.lockfile_path {
+                                Some(_) => fs::metadata(self.package_lockfile_path())
+                                    .and_then(|m| m.modified())
+                                    .ok(),
+                                None => None,
+                            };
+                            let build_script_mtime = match &self.build_script {
+                                Some(_) => {
+                                    Some(fs::metadata(self.build_script_path())?.modified()?)
+                                }
+                                None => None,
+                            };
+                            // Cargo would rebuild for a changed library script, but wouldn't be asked to.
+                            let mut library_mtimes = Vec::with_capacity(self.libraries.len());
+                            for library in &self.libraries {
+                                library_mtimes
+                                    .push(fs::metadata(&library.script_path)?.modified()?);
+                            }
                             if built_binary_time.cmp(&script_mtime).is_ge()
                                 && built_binary_time.cmp(&manifest_mtime).is_ge()
+                                && lockfile_mtime
+                                    .map_or(true, |t| built_binary_time.cmp(&t).is_ge())
+                                && build_script_mtime
+                                    .map_or(true, |t| built_binary_time.cmp(&t).is_ge())
+                                && library_mtimes
+                                    .iter()
+                                    .all(|t| built_binary_time.cmp(t).is_ge())
                             {
                                 debug!("Keeping old binary");
                                 return execute_command();
@@ -448,46 +662,104 @@ impl InputAction {
             }
         }
 
-        let maybe_toolchain_version = self.toolchain_version.as_deref();
+        let cargo_command = || {
+            let mut cmd = Command::new("cargo");
+            if let Some(toolchain_version) = &self.toolchain_version {
+                cmd.arg(format!("+{}", toolchain_version));
+            }
+            cmd.arg(self.build_kind.exec_command());
 
-        let mut cmd = Command::new("cargo");
-        if let Some(toolchain_version) = maybe_toolchain_version {
-            cmd.arg(format!("+{}", toolchain_version));
-        }
-        cmd.arg(self.build_kind.exec_command());
+            if matches!(self.build_kind, BuildKind::Normal) && !self.cargo_output {
+                cmd.arg("-q");
+            }
 
-        if matches!(self.build_kind, BuildKind::Normal) && !self.cargo_output {
-            cmd.arg("-q");
-        }
+            cmd.current_dir(&self.pkg_path);
 
-        cmd.current_dir(&self.pkg_path);
+            if platform::force_cargo_color() {
+                cmd.arg("--color").arg("always");
+            }
 
-        if platform::force_cargo_color() {
-            cmd.arg("--color").arg("always");
-        }
+            let cargo_target_dir = format!("{}", platform::binary_cache_path().display(),);
+            cmd.arg("--target-dir");
+            cmd.arg(cargo_target_dir);
 
-        let cargo_target_dir = format!("{}", platform::binary_cache_path().display(),);
-        cmd.arg("--target-dir");
-        cmd.arg(cargo_target_dir);
+            if release_mode {
+                cmd.arg("--release");
+            }
 
-        if release_mode {
-            cmd.arg("--release");
-        }
+            if !self.features.is_empty() {
+                cmd.arg("--features").arg(self.features.join(","));
+            }
 
-        if matches!(self.build_kind, BuildKind::Normal) {
-            if cmd.status()?.code() == Some(0) {
-                cmd = execute_command()?;
-            } else {
-                return Err(MainError::OtherOwned("Could not execute cargo".to_string()));
+            if self.locked {
+                cmd.arg("--locked");
             }
+            if self.frozen {
+                cmd.arg("--frozen");
+            }
+            if self.offline {
+                cmd.arg("--offline");
+            }
+            cmd
+        };
+
+        // Tests and benchmarks are built before cargo is left to run them, so that the lockfile can be saved for them too.
+        let mut build = cargo_command();
+        if !matches!(self.build_kind, BuildKind::Normal) {
+            build.arg("--no-run");
+        }
+        let (success, missing_crates) = if self.offline {
+            // Cargo's messages are passed on, but also looked at, to tell crates missing from the cache from a script which doesn't compile.
+            let output = build
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::piped())
+                .output()?;
+            std::io::stderr().write_all(&output.stderr)?;
+            (
+                output.status.success(),
+                is_missing_crates(&String::from_utf8_lossy(&output.stderr)),
+            )
         } else {
-            cmd.args(script_args.iter());
+            (build.status()?.success(), false)
+        };
+        if !success {
+            return if missing_crates {
+                Err(MainError::OtherBorrowed(
+                    "Could not execute cargo offline. If the script needs crates which are not in \
+                    Cargo's local cache, download them first by running `rust-script --fetch` on \
+                    the script on a machine with network access",
+                ))
+            } else {
+                Err(MainError::OtherOwned("Could not execute cargo".to_string()))
+            };
         }
+        self.save_lockfile()?;
+
+        let cmd = if matches!(self.build_kind, BuildKind::Normal) {
+            execute_command()?
+        } else {
+            let mut cmd = cargo_command();
+            cmd.args(script_args.iter());
+            cmd
+        };
 
         Ok(cmd)
     }
 }
 
+/**
+Returns `true` if cargo failed offline for want of crates or of the registry, rather than because the script doesn't compile.
+*/
+fn is_missing_crates(cargo_stderr: &str) -> bool {
+    [
+        "no matching package",
+        "attempting to make an HTTP request, but --offline was specified",
+        "failed to download",
+    ]
+    .iter()
+    .any(|message| cargo_stderr.contains(message))
+}
+
 /**
 For the given input, this constructs the package metadata and checks the cache to see what should be done.
 */
@@ -496,10 +768,11 @@ fn decide_action_for(
     deps: Vec<(String, String)>,
     prelude: Vec<String>,
     args: &Args,
+    wildcard_policy: dependency::WildcardPolicy,
 ) -> MainResult<InputAction> {
     let input_id = {
         let deps_iter = deps.iter().map(|(n, v)| (n as &str, v as &str));
-        input.compute_id(deps_iter)
+        input.compute_id(deps_iter, args.block.as_deref())
     };
     info!("id: {:?}", input_id);
 
@@ -517,34 +790,63 @@ fn decide_action_for(
     info!("pkg_path: {:?}", pkg_path);
     info!("using_cache: {:?}", using_cache);
 
-    let toolchain_version = args
-        .toolchain_version
-        .clone()
-        .or_else(|| match args.build_kind {
-            BuildKind::Bench => Some("nightly".into()),
-            _ => None,
-        });
-
     let script_name = format!("{}.rs", input.safe_name());
 
-    let (mani_str, script_path, script_str) = manifest::split_input(
-        input,
-        input.base_path(),
-        &deps,
-        &prelude,
-        &pkg_path,
-        &bin_name,
-        &script_name,
-        toolchain_version.clone(),
-    )?;
+    let (mani_str, script_path, script_str, build_script, libraries, settings) =
+        manifest::split_input(
+            input,
+            input.base_path(),
+            &deps,
+            &prelude,
+            &pkg_path,
+            &bin_name,
+            &script_name,
+            args.toolchain_version.clone(),
+            wildcard_policy,
+            args.block.as_deref(),
+        )?;
+
+    // Settings from the script's manifest only apply where the command line doesn't say otherwise.
+    let toolchain_version = match args.toolchain_version.clone().or(settings.toolchain) {
+        None if settings.toolchain_file == Some(true) => {
+            find_toolchain_file_channel(input.base_path())?
+        }
+        toolchain => toolchain,
+    }
+    .or_else(|| match args.build_kind {
+        BuildKind::Bench => Some("nightly".into()),
+        _ => None,
+    });
 
     // Forcibly override some flags based on build kind.
     let debug = match args.build_kind {
-        BuildKind::Normal => args.debug,
+        BuildKind::Normal => args.debug || settings.debug == Some(true),
         BuildKind::Test => true,
         BuildKind::Bench => false,
     };
 
+    let script_args = if args.script_args.is_empty() && matches!(args.build_kind, BuildKind::Normal)
+    {
+        settings.args
+    } else {
+        args.script_args.clone()
+    };
+
+    // The script's own lockfile is used if it has one, or asks for one.
+    let lockfile_path = input.lockfile_path().filter(|path| {
+        path.exists() || settings.lockfile == Some(true) || args.locked || args.frozen
+    });
+    if let Some(path) = &lockfile_path {
+        if (args.locked || args.frozen) && !path.exists() {
+            return Err(format!(
+                "`--{}` requires a lockfile, but {} does not exist; set `lockfile = true` in `[package.metadata.rustscript]` and run the script once to create it",
+                if args.frozen { "frozen" } else { "locked" },
+                path.display()
+            )
+            .into());
+        }
+    }
+
     Ok(InputAction {
         cargo_output: args.cargo_output,
         force_compile: args.force,
@@ -556,13 +858,58 @@ fn decide_action_for(
         debug,
         manifest: mani_str,
         script: script_str,
+        build_script,
+        libraries,
         build_kind: args.build_kind,
+        features: settings.features,
+        wrapper: args.wrapper.clone().or(settings.wrapper),
+        script_args,
+        env: settings.env,
+        lockfile_path,
+        locked: args.locked,
+        frozen: args.frozen,
+        offline: args.offline,
         bin_name,
         #[cfg(unix)]
-        original_script_path: args.script.clone(),
+        original_script_path: args.script.clone().filter(|_| !args.stdin),
     })
 }
 
+/**
+Looks for a `rust-toolchain.toml` (or legacy `rust-toolchain`) file in the given directory or its ancestors, the same way `rustup` does, and returns the channel it specifies.
+*/
+fn find_toolchain_file_channel(dir: &Path) -> MainResult<Option<String>> {
+    for dir in dir.ancestors() {
+        for file_name in ["rust-toolchain.toml", "rust-toolchain"] {
+            let path = dir.join(file_name);
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(MainError::Io(e)),
+            };
+            info!("using toolchain file {:?}", path);
+
+            // The legacy format is just the name of the channel.
+            if file_name == "rust-toolchain" && !content.contains('[') {
+                return Ok(Some(content.trim().into()));
+            }
+
+            let toml: toml::value::Table = toml::from_str(&content).map_err(|e| {
+                MainError::Tag(
+                    format!("could not parse {}", path.display()).into(),
+                    Box::new(MainError::Other(Box::new(e))),
+                )
+            })?;
+            return Ok(toml
+                .get("toolchain")
+                .and_then(|t| t.get("channel"))
+                .and_then(|c| c.as_str())
+                .map(Into::into));
+        }
+    }
+    Ok(None)
+}
+
 /// Attempts to locate the script specified by the given path.
 fn find_script(path: &Path) -> Option<(PathBuf, fs::File)> {
     if let Ok(file) = fs::File::open(path) {
@@ -581,6 +928,38 @@ fn find_script(path: &Path) -> Option<(PathBuf, fs::File)> {
     None
 }
 
+/**
+Reads a script from stdin, up to the end of input or a line holding just `__END__`.
+
+The script is read a byte at a time, so that whatever follows `__END__` is left on stdin for the script itself.
+*/
+fn read_stdin_script() -> MainResult<String> {
+    let mut stdin = platform::unbuffered_stdin()?;
+    let mut script = Vec::new();
+    let mut line_start = 0;
+    let mut byte = [0];
+    loop {
+        match stdin.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+        script.push(byte[0]);
+        if byte[0] == b'\n' {
+            let line = &script[line_start..];
+            if line == b"__END__\n" || line == b"__END__\r\n" {
+                script.truncate(line_start);
+                break;
+            }
+            line_start = script.len();
+        }
+    }
+
+    String::from_utf8(script)
+        .map_err(|_| MainError::OtherBorrowed("the script read from stdin is not valid UTF-8"))
+}
+
 /**
 Represents an input source for a script.
 */
@@ -593,19 +972,33 @@ pub enum Input {
     */
     File(String, PathBuf, String, PathBuf),
 
+    /**
+    The input is a script read from stdin.
+
+    The tuple members are: the script contents, base path.
+    */
+    Stdin(String, PathBuf),
+
     /**
     The input is an expression.
 
-    The tuple member is: the script contents, base path.
+    The tuple members are: the script contents, how its value is printed, base path.
     */
-    Expr(String, PathBuf),
+    Expr(String, output_format::OutputFormat, PathBuf),
 
     /**
     The input is a loop expression.
 
-    The tuple member is: the script contents, whether the `--count` flag was given, base path.
+    The tuple member is: the script contents, how the loop is run, base path.
+    */
+    Loop(String, loop_options::LoopOptions, PathBuf),
+
+    /**
+    The input is the program replaying a `--repl` session, up to and including the latest input.
+
+    The tuple members are: the script contents, the ID of the session, base path.
     */
-    Loop(String, bool, PathBuf),
+    Repl(String, u32, PathBuf),
 }
 
 impl Input {
@@ -617,11 +1010,26 @@ impl Input {
 
         match self {
             File(_, path, _, _) => Some(path),
+            Stdin(..) => None,
             Expr(..) => None,
             Loop(..) => None,
+            Repl(..) => None,
         }
     }
 
+    /**
+    Return the path of the lockfile kept next to the script, if it has one.
+
+    This is the script's file name with `.lock` appended, *e.g.* `script.rs.lock`.
+    */
+    pub fn lockfile_path(&self) -> Option<PathBuf> {
+        self.path().map(|path| {
+            let mut lockfile_path = path.as_os_str().to_owned();
+            lockfile_path.push(".lock");
+            lockfile_path.into()
+        })
+    }
+
     /**
     Return the "safe name" for the input.  This should be filename-safe.
 
@@ -632,8 +1040,10 @@ impl Input {
 
         match self {
             File(name, _, _, _) => name,
+            Stdin(..) => "stdin",
             Expr(..) => "expr",
             Loop(..) => "loop",
+            Repl(..) => "repl",
         }
     }
 
@@ -672,15 +1082,18 @@ impl Input {
     pub fn base_path(&self) -> &PathBuf {
         match self {
             Input::File(_, _, _, base_path)
-            | Input::Expr(_, base_path)
-            | Input::Loop(_, _, base_path) => base_path,
+            | Input::Stdin(_, base_path)
+            | Input::Expr(_, _, base_path)
+            | Input::Loop(_, _, base_path)
+            | Input::Repl(_, _, base_path) => base_path,
         }
     }
 
     // Compute the package ID for the input.
     // This is used as the name of the cache folder into which the Cargo package
     // will be generated.
-    pub fn compute_id<'dep, DepIt>(&self, deps: DepIt) -> OsString
+    // `block` is the code block picked out of a Markdown script, if any.
+    pub fn compute_id<'dep, DepIt>(&self, deps: DepIt, block: Option<&str>) -> OsString
     where
         DepIt: IntoIterator<Item = (&'dep str, &'dep str)>,
     {
@@ -704,6 +1117,11 @@ impl Input {
 
                 // Hash the path to the script.
                 hasher.update(&*path.to_string_lossy());
+                // Each block of a Markdown script is a program of its own.
+                if let Some(block) = block {
+                    hasher.update("block:");
+                    hasher.update(block);
+                }
                 let mut digest = format!("{:x}", hasher.finalize());
                 digest.truncate(consts::ID_DIGEST_LEN_MAX);
 
@@ -711,9 +1129,11 @@ impl Input {
                 id.push(&*digest);
                 id
             }
-            Expr(content, _) => {
+            Stdin(content, _) => {
                 let mut hasher = hash_deps();
 
+                // Keep scripts apart from expressions with the same text.
+                hasher.update("stdin:");
                 hasher.update(content);
                 let mut digest = format!("{:x}", hasher.finalize());
                 digest.truncate(consts::ID_DIGEST_LEN_MAX);
@@ -722,17 +1142,41 @@ impl Input {
                 id.push(&*digest);
                 id
             }
-            Loop(content, count, _) => {
+            Expr(content, format, _) => {
                 let mut hasher = hash_deps();
 
-                // Make sure to include the [non-]presence of the `--count` flag in the flag, since it changes the actual generated script output.
-                hasher.update("count:");
-                hasher.update(if *count { "true;" } else { "false;" });
+                format.hash(&mut hasher);
+                hasher.update(content);
+                let mut digest = format!("{:x}", hasher.finalize());
+                digest.truncate(consts::ID_DIGEST_LEN_MAX);
+
+                let mut id = OsString::new();
+                id.push(&*digest);
+                id
+            }
+            Loop(content, options, _) => {
+                let mut hasher = hash_deps();
+
+                // Make sure to include the options, such as the [non-]presence of the `--count` flag, since they change the actual generated script output.
+                options.hash(&mut hasher);
 
                 hasher.update(content);
                 let mut digest = format!("{:x}", hasher.finalize());
                 digest.truncate(consts::ID_DIGEST_LEN_MAX);
 
+                let mut id = OsString::new();
+                id.push(&*digest);
+                id
+            }
+            Repl(_, session_id, _) => {
+                let mut hasher = Sha1::new();
+
+                // Every input of a session goes into the same package, so only the changes get rebuilt, but other sessions each get their own.
+                hasher.update("repl:");
+                hasher.update(session_id.to_string());
+                let mut digest = format!("{:x}", hasher.finalize());
+                digest.truncate(consts::ID_DIGEST_LEN_MAX);
+
                 let mut id = OsString::new();
                 id.push(&*digest);
                 id
@@ -770,6 +1214,44 @@ fn overwrite_file(path: &Path, content: &str) -> MainResult<()> {
     Ok(())
 }
 
+#[test]
+fn test_find_toolchain_file_channel() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let sub_dir = dir.path().join("sub");
+    fs::create_dir(&sub_dir).unwrap();
+
+    assert_eq!(find_toolchain_file_channel(&sub_dir).unwrap(), None);
+
+    fs::write(dir.path().join("rust-toolchain"), "nightly-2020-01-01\n").unwrap();
+    assert_eq!(
+        find_toolchain_file_channel(&sub_dir).unwrap(),
+        Some("nightly-2020-01-01".into())
+    );
+
+    fs::write(
+        sub_dir.join("rust-toolchain.toml"),
+        "[toolchain]\nchannel = \"1.74\"\ncomponents = [\"rustfmt\"]\n",
+    )
+    .unwrap();
+    assert_eq!(
+        find_toolchain_file_channel(&sub_dir).unwrap(),
+        Some("1.74".into())
+    );
+}
+
+#[test]
+fn test_is_missing_crates() {
+    assert!(is_missing_crates(
+        "error: no matching package named `not-a-crate` found\nlocation searched: registry `crates-io`"
+    ));
+    assert!(is_missing_crates(
+        "error: failed to download `regex v1.10.0`\n\nCaused by:\n  attempting to make an HTTP request, but --offline was specified"
+    ));
+    assert!(!is_missing_crates(
+        "error[E0425]: cannot find value `not_defined` in this scope"
+    ));
+}
+
 #[test]
 fn test_package_name() {
     let input = Input::File(
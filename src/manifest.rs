@@ -5,22 +5,25 @@ use pulldown_cmark::TagEnd;
 use regex;
 
 use self::regex::Regex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::path::PathBuf;
 
 use crate::consts;
+use crate::dependency::WildcardPolicy;
 use crate::error::{MainError, MainResult};
+use crate::library::{self, Library};
+use crate::output_format::OutputFormat;
 use crate::templates;
 use crate::Input;
 use log::{error, info};
 
 /**
-Splits input into a complete Cargo manifest and unadultered Rust source.
+Splits input into a complete Cargo manifest and unadultered Rust source, along with the runner settings given in the manifest.
 
 Unless we have prelude items to inject, in which case it will be *slightly* adulterated.
 */
-#[allow(clippy::too_many_arguments)]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn split_input(
     input: &Input,
     base_path: &Path,
@@ -30,49 +33,93 @@ pub fn split_input(
     bin_name: &str,
     script_name: &str,
     toolchain: Option<String>,
-) -> MainResult<(String, PathBuf, Option<String>)> {
-    fn contains_main_method(source: &str) -> bool {
-        let re_main: Regex =
-            Regex::new(r#"(?m)^ *(pub )?(async )?(extern "C" )?fn main *\("#).unwrap();
-        re_main.is_match(source)
-    }
-
+    wildcard_policy: WildcardPolicy,
+    block: Option<&str>,
+) -> MainResult<(
+    String,
+    PathBuf,
+    Option<String>,
+    Option<String>,
+    Vec<Library>,
+    ScriptSettings,
+)> {
     let source_in_package = package_path.as_ref().join(script_name);
+    let build_script = match input {
+        Input::File(_, path, content, _) if is_markdown(path) => {
+            find_markdown_build_script(content)
+        }
+        Input::File(_, _, content, _) | Input::Stdin(content, _) => {
+            find_embedded_build_script(strip_shebang(content))
+        }
+        _ => None,
+    };
     let (part_mani, source_path, source, template, sub_prelude) = match input {
-        Input::File(_, path, content, _) => {
+        Input::File(_, path, content, _) if is_markdown(path) => {
+            let (manifest, mut source) = split_markdown(content, block)?;
+            let template = if contains_main_method(&source) {
+                consts::FILE_TEMPLATE
+            } else {
+                // The template starts the source on its third line, so drop two of the blank lines standing in for the prose to keep the lines matching up with the Markdown.
+                let blank_lines = source.bytes().take(2).take_while(|&b| b == b'\n').count();
+                source.drain(..blank_lines);
+                consts::FILE_NO_MAIN_TEMPLATE
+            };
+            let source = anchor_relative_paths(&source, script_dir(path));
+            (manifest, source_in_package, source, Some(template), false)
+        }
+        Input::File(_, _, content, _) | Input::Stdin(content, _) => {
             assert_eq!(prelude_items.len(), 0);
+            // A script read from stdin has no file to compile in place, or to resolve relative paths against.
+            let path = input.path();
+            let dir = path.map_or(input.base_path().as_path(), script_dir);
+            let full_content = content;
             let content = strip_shebang(content);
             let (manifest, source) =
                 find_embedded_manifest(content).unwrap_or((Manifest::Toml(""), content));
 
-            if contains_main_method(content) {
-                (manifest, path.clone(), source.to_string(), None, false)
+            if source.len() < content.len() {
+                // The manifest was cut out of the source (a frontmatter block), which rustc won't accept, so we have to compile a copy instead of the original file.
+                let blanked = blank_manifest(full_content, source);
+                let template = if contains_main_method(source) {
+                    consts::FILE_TEMPLATE
+                } else {
+                    consts::FILE_NO_MAIN_TEMPLATE
+                };
+                let source = anchor_relative_paths(&blanked, dir);
+                (manifest, source_in_package, source, Some(template), false)
+            } else if contains_main_method(content) {
+                match path {
+                    Some(path) => (manifest, path.into(), source.to_string(), None, false),
+                    None => (
+                        manifest,
+                        source_in_package,
+                        anchor_relative_paths(full_content, dir),
+                        Some(consts::FILE_TEMPLATE),
+                        false,
+                    ),
+                }
             } else {
                 (
                     manifest,
                     source_in_package,
-                    content.to_string(),
+                    anchor_relative_paths(content, dir),
                     Some(consts::FILE_NO_MAIN_TEMPLATE),
                     false,
                 )
             }
         }
-        Input::Expr(content, _) => (
+        Input::Expr(content, _, _) | Input::Repl(content, _, _) => (
             Manifest::Toml(""),
             source_in_package,
             content.to_string(),
             Some(consts::EXPR_TEMPLATE),
             true,
         ),
-        Input::Loop(content, count, _) => (
+        Input::Loop(content, options, _) => (
             Manifest::Toml(""),
             source_in_package,
             content.to_string(),
-            Some(if *count {
-                consts::LOOP_COUNT_TEMPLATE
-            } else {
-                consts::LOOP_TEMPLATE
-            }),
+            Some(options.template()),
             true,
         ),
     };
@@ -82,6 +129,16 @@ pub fn split_input(
 
     subs.insert(consts::SCRIPT_BODY_SUB, &source[..]);
 
+    let template_subs = match input {
+        Input::Expr(_, format, _) => format.template_subs(),
+        Input::Repl(..) => OutputFormat::Debug.template_subs(),
+        Input::Loop(_, options, _) => options.template_subs()?,
+        _ => Vec::new(),
+    };
+    for (name, value) in &template_subs {
+        subs.insert(name, value);
+    }
+
     if sub_prelude {
         prelude_str =
             String::with_capacity(prelude_items.iter().map(|i| i.len() + 1).sum::<usize>());
@@ -95,7 +152,14 @@ pub fn split_input(
     let source = template
         .map(|template| templates::expand(template, &subs))
         .transpose()?;
-    let part_mani = part_mani.into_toml()?;
+    let script_form = part_mani.form_name();
+    let part_mani = match input {
+        Input::File(_, path, content, _) => {
+            part_mani.into_toml(content, &path.display().to_string())?
+        }
+        Input::Stdin(content, _) => part_mani.into_toml(content, "<stdin>")?,
+        _ => part_mani.into_toml("", "")?,
+    };
     info!("part_mani: {:?}", part_mani);
     info!("source: {:?}", source);
 
@@ -108,19 +172,574 @@ pub fn split_input(
     };
 
     // It's-a mergin' time!
-    let def_mani = default_manifest(bin_name, source_path_from_package, toolchain);
+    let def_mani = default_manifest(bin_name, source_path_from_package);
     let dep_mani = deps_manifest(deps)?;
 
-    let mani = merge_manifest(def_mani, part_mani)?;
-    let mani = merge_manifest(mani, dep_mani)?;
+    check_dep_conflicts(&part_mani, &dep_mani)?;
+    let script_deps: HashSet<String> = dependency_entries(&part_mani)
+        .into_iter()
+        .map(|(key, _, _)| key)
+        .collect();
+
+    let mani = merge_manifest(def_mani, dep_mani)?;
+    let mut mani = merge_manifest(mani, part_mani)?;
+
+    // A toolchain given on the command line wins over one given by the script.
+    if let Some(toolchain) = toolchain {
+        mani = merge_manifest(mani, toolchain_manifest(toolchain))?;
+    }
 
     // Fix up relative paths.
-    let mani = fix_manifest_paths(mani, base_path)?;
+    let mut mani = fix_manifest_paths(mani, base_path)?;
+
+    if build_script.is_some() {
+        add_build_script(&mut mani)?;
+    }
+
+    let libraries = library::resolve_script_dependencies(&mut mani, input.path(), wildcard_policy)?;
+
+    check_unpinned_deps(
+        &mani,
+        |key| {
+            if script_deps.contains(key) {
+                script_form
+            } else {
+                "the command line"
+            }
+        },
+        wildcard_policy,
+    )?;
+
+    let settings = ScriptSettings::from_manifest(&mani)?;
+    info!("settings: {:?}", settings);
 
     let mani_str = format!("{}", mani);
     info!("manifest: {}", mani_str);
 
-    Ok((mani_str, source_path, source))
+    Ok((
+        mani_str,
+        source_path,
+        source,
+        build_script,
+        libraries,
+        settings,
+    ))
+}
+
+/**
+Points the manifest at the `build.rs` written for an embedded build script.
+*/
+fn add_build_script(mani: &mut toml::value::Table) -> MainResult<()> {
+    let package = mani
+        .entry("package")
+        .or_insert_with(|| toml::value::Table::new().into())
+        .as_table_mut()
+        .ok_or("`package` in the manifest must be a table")?;
+    if package.contains_key("build") {
+        return Err(
+            "the script has an embedded build script, so `package.build` must not be set".into(),
+        );
+    }
+    package.insert("build".into(), "build.rs".into());
+    Ok(())
+}
+
+/**
+Returns `true` if the source defines a `main` function, and doesn't need to be wrapped in one.
+*/
+pub fn contains_main_method(source: &str) -> bool {
+    let re_main: Regex = Regex::new(r#"(?m)^ *(pub )?(async )?(extern "C" )?fn main *\("#).unwrap();
+    re_main.is_match(source)
+}
+
+/**
+Returns `rest`, the part of `content` after its embedded manifest, with everything before it blanked out rather than dropped, so that line numbers in diagnostics still match the script.
+*/
+pub fn blank_manifest(content: &str, rest: &str) -> String {
+    let removed = &content[..content.len() - rest.len()];
+    let mut blanked = "\n".repeat(removed.matches('\n').count());
+    blanked.push_str(rest);
+    blanked
+}
+
+/// Returns the directory of the script at `path`, which relative module and include paths are resolved against.
+pub fn script_dir(path: &Path) -> &Path {
+    path.parent()
+        .expect("couldn't get parent directory for script path")
+}
+
+/**
+Rewrites relative module and include paths in a script, so that they still resolve against the directory of the script when it is compiled from a copy in the package.
+
+`include!`, `include_str!` and `include_bytes!` of a string literal, and `#[path]` attributes, are given absolute paths.  A `mod name;` is given a `#[path]` to `name.rs`, or to `name/mod.rs` if there is no `name.rs`.  rustc looks for the submodules of a file given with `#[path]` next to it, though, so a `name.rs` with a `name/` directory is instead wrapped in an inline module with a `#[path]` to its directory, which glob imports the items of its parent so that `super::` in the module still finds them.  Everything stays on the same line, so line numbers in diagnostics still match the script.
+*/
+pub fn anchor_relative_paths(source: &str, dir: &Path) -> String {
+    let re_include: Regex =
+        Regex::new(r#"\b(include|include_str|include_bytes)!\s*\(\s*"([^"]*)"\s*\)"#).unwrap();
+    let literal = |path: &Path| format!("{:?}", path.display().to_string());
+    let masked = blank_comments_and_literals(source);
+
+    // Pairs of the range of `source` to replace and what to replace it with, in order.
+    let mut edits: Vec<(std::ops::Range<usize>, String)> = Vec::new();
+    for cap in re_include.captures_iter(&masked) {
+        let range = cap.get(2).unwrap().range();
+        let path = Path::new(&source[range.clone()]);
+        if !source[range.clone()].contains('\\') && path.is_relative() {
+            // Widen the range to take in the quotes.
+            edits.push((range.start - 1..range.end + 1, literal(&dir.join(path))));
+        }
+    }
+    for decl in find_mod_declarations(source) {
+        // Modules nested in inline modules are looked for in directories named after them.
+        let mod_dir = decl
+            .parents
+            .iter()
+            .fold(dir.to_path_buf(), |d, p| d.join(p));
+        match decl.path_attr {
+            // rustc resolves these relative to the file, not the module.
+            Some(range) if Path::new(&source[range.clone()]).is_relative() => {
+                let path = mod_dir.join(&source[range.clone()]);
+                edits.push((range.start - 1..range.end + 1, literal(&path)));
+            }
+            Some(_) => {}
+            None => {
+                let file_name = decl.name.trim_start_matches("r#");
+                let file = mod_dir.join(format!("{}.rs", file_name));
+                if !file.exists() {
+                    let path = mod_dir.join(file_name).join("mod.rs");
+                    edits.push((
+                        decl.range.start..decl.range.start,
+                        format!("#[path = {}] ", literal(&path)),
+                    ));
+                } else if !mod_dir.join(file_name).is_dir() {
+                    edits.push((
+                        decl.range.start..decl.range.start,
+                        format!("#[path = {}] ", literal(&file)),
+                    ));
+                } else {
+                    edits.push((
+                        decl.range.clone(),
+                        format!(
+                            "#[path = {}] mod __rust_script_{} {{ #[allow(unused_imports)] use super::*; pub mod {}; }} {}use __rust_script_{}::{};",
+                            literal(&mod_dir),
+                            file_name,
+                            decl.name,
+                            decl.visibility,
+                            file_name,
+                            decl.name
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+    edits.sort_by_key(|(range, _)| range.start);
+
+    let mut result = String::with_capacity(source.len());
+    let mut anchor = 0;
+    for (range, replacement) in edits {
+        result.push_str(&source[anchor..range.start]);
+        result.push_str(&replacement);
+        anchor = range.end;
+    }
+    result.push_str(&source[anchor..]);
+    result
+}
+
+/**
+A `mod name;` declaration of a module in another file, as found by `find_mod_declarations`.
+*/
+#[derive(Debug, PartialEq)]
+pub struct ModDeclaration<'s> {
+    /// Where the declaration is in the source, from its visibility (or `mod`) to the `;`.
+    pub range: std::ops::Range<usize>,
+    /// Its visibility, with the whitespace after it, or `""`.
+    pub visibility: &'s str,
+    /// Its name, which may be a raw identifier such as `r#type`.
+    pub name: &'s str,
+    /// Where the path of a `#[path]` attribute directly before it is, without the quotes.
+    pub path_attr: Option<std::ops::Range<usize>>,
+    /// The names of the inline modules it is in, outermost first.
+    pub parents: Vec<&'s str>,
+}
+
+/**
+Finds the `mod name;` declarations in Rust source, ignoring any in comments and string literals.
+*/
+pub fn find_mod_declarations(source: &str) -> Vec<ModDeclaration<'_>> {
+    let re_mod: Regex = Regex::new(
+        r#"(?:#\[\s*path\s*=\s*"([^"]*)"\s*\]\s*)?\b((?:pub(?:\s*\([^)]*\))?\s+)?)mod\s+((?:r#)?[A-Za-z_][A-Za-z0-9_]*)\s*([;{])"#,
+    )
+    .unwrap();
+    let masked = blank_comments_and_literals(source);
+
+    let mut decls = Vec::new();
+    // For each open brace, the name of the inline module it opens, if it does.
+    let mut braces: Vec<Option<&str>> = Vec::new();
+    let mut caps = re_mod.captures_iter(&masked).peekable();
+    let mut pos = 0;
+    while pos < masked.len() {
+        if let Some(cap) = caps.next_if(|cap| cap.get(0).unwrap().start() == pos) {
+            let visibility = cap.get(2).unwrap();
+            let name = &source[cap.get(3).unwrap().range()];
+            if &cap[4] == "{" {
+                braces.push(Some(name));
+            } else {
+                decls.push(ModDeclaration {
+                    range: visibility.start()..cap.get(0).unwrap().end(),
+                    visibility: &source[visibility.range()],
+                    name,
+                    path_attr: cap.get(1).map(|path| path.range()),
+                    parents: braces.iter().flatten().copied().collect(),
+                });
+            }
+            pos = cap.get(0).unwrap().end();
+            continue;
+        }
+        match masked.as_bytes()[pos] {
+            b'{' => braces.push(None),
+            b'}' => {
+                braces.pop();
+            }
+            _ => {}
+        }
+        pos += 1;
+        // Skip matches which start inside the last one, such as a `mod` right after a `#[path]` it couldn't be matched along with.
+        while caps
+            .next_if(|cap| cap.get(0).unwrap().start() < pos)
+            .is_some()
+        {}
+    }
+    decls
+}
+
+/**
+Returns a copy of Rust source with the comments, and the contents of string and character literals, replaced with spaces.  Line breaks and the offsets of everything else stay the same.
+*/
+fn blank_comments_and_literals(source: &str) -> String {
+    let bytes = source.as_bytes();
+    let mut masked = bytes.to_vec();
+    let mut blank = |range: std::ops::Range<usize>| {
+        for b in &mut masked[range] {
+            if *b != b'\n' {
+                *b = b' ';
+            }
+        }
+    };
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        // The lengths of the `r` or `br`, and of the `#`s, which start a raw string here.
+        let raw_string = match rest {
+            [b'r', ..] => Some(1),
+            [b'b', b'r', ..] => Some(2),
+            _ => None,
+        }
+        .filter(|_| i == 0 || !is_ident(bytes[i - 1]))
+        .map(|prefix| {
+            (
+                prefix,
+                rest[prefix..].iter().take_while(|&&b| b == b'#').count(),
+            )
+        })
+        .filter(|&(prefix, hashes)| rest.get(prefix + hashes) == Some(&b'"'));
+        if rest.starts_with(b"//") {
+            let end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(bytes.len(), |n| i + n);
+            blank(i..end);
+            i = end;
+        } else if rest.starts_with(b"/*") {
+            let mut depth = 0;
+            let mut j = i;
+            while j < bytes.len() {
+                if bytes[j..].starts_with(b"/*") {
+                    depth += 1;
+                    j += 2;
+                } else if bytes[j..].starts_with(b"*/") {
+                    depth -= 1;
+                    j += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    j += 1;
+                }
+            }
+            blank(i..j);
+            i = j;
+        } else if let Some((prefix, hashes)) = raw_string {
+            // A raw string, which ends at a quote followed by as many `#` as it started with.
+            let start = i + prefix + hashes + 1;
+            let mut closing = vec![b'"'];
+            closing.extend(std::iter::repeat(b'#').take(hashes));
+            let end = bytes[start..]
+                .windows(closing.len())
+                .position(|w| w == closing.as_slice())
+                .map_or(bytes.len(), |n| start + n);
+            blank(start..end);
+            i = (end + closing.len()).min(bytes.len());
+        } else if rest[0] == b'"' {
+            let start = i + 1;
+            let mut j = start;
+            while j < bytes.len() && bytes[j] != b'"' {
+                j += if bytes[j] == b'\\' { 2 } else { 1 };
+            }
+            let end = j.min(bytes.len());
+            blank(start..end);
+            i = end + 1;
+        } else if rest[0] == b'\'' {
+            // A character literal, unless it is a lifetime or a label.
+            let len = match rest.get(1) {
+                Some(b'\\') => rest
+                    .get(3..)
+                    .and_then(|r| r.iter().position(|&b| b == b'\''))
+                    .map(|n| n + 3),
+                Some(_) => {
+                    let c = source[i + 1..].chars().next().unwrap();
+                    (rest.get(1 + c.len_utf8()) == Some(&b'\'')).then_some(c.len_utf8() + 1)
+                }
+                None => None,
+            };
+            match len {
+                Some(len) => {
+                    blank(i + 1..i + len);
+                    i += len + 1;
+                }
+                None => i += 1,
+            }
+        } else {
+            i += 1;
+        }
+    }
+    String::from_utf8(masked).expect("only whole characters are blanked")
+}
+
+#[cfg(unix)]
+#[test]
+fn test_anchor_relative_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    std::fs::write(dir.join("helper.rs"), "").unwrap();
+    std::fs::write(dir.join("type.rs"), "").unwrap();
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(dir.join("nested.rs"), "pub mod inner;").unwrap();
+    let arp = |s: &str| anchor_relative_paths(s, dir);
+    let d = dir.display();
+
+    assert_eq!(
+        arp(r#"let s = include_str!("data.txt");"#),
+        format!(r#"let s = include_str!("{}/data.txt");"#, d)
+    );
+    assert_eq!(
+        arp(r#"include!("/abs/gen.rs"); include_bytes!(concat!("a", "b"));"#),
+        r#"include!("/abs/gen.rs"); include_bytes!(concat!("a", "b"));"#
+    );
+    assert_eq!(
+        arp("mod helper;\n    pub(crate) mod r#type;\nmod other;\n"),
+        format!(
+            "#[path = \"{0}/helper.rs\"] mod helper;\n    #[path = \"{0}/type.rs\"] pub(crate) mod r#type;\n#[path = \"{0}/other/mod.rs\"] mod other;\n",
+            d
+        )
+    );
+    assert_eq!(
+        arp("pub mod nested;"),
+        format!(
+            "#[path = \"{0}\"] mod __rust_script_nested {{ #[allow(unused_imports)] use super::*; pub mod nested; }} pub use __rust_script_nested::nested;",
+            d
+        )
+    );
+    assert_eq!(
+        arp("mod outer {\n    mod helper;\n}"),
+        format!(
+            "mod outer {{\n    #[path = \"{}/outer/helper/mod.rs\"] mod helper;\n}}",
+            d
+        )
+    );
+    assert_eq!(
+        arp("#[path = \"other.rs\"]\nmod helper;\n#[path = \"/abs.rs\"] mod abs;"),
+        format!(
+            "#[path = \"{}/other.rs\"]\nmod helper;\n#[path = \"/abs.rs\"] mod abs;",
+            d
+        )
+    );
+    // Neither comments nor strings are code.
+    let untouched = "// mod helper;\n/* mod helper; /* nested */ mod helper; */\nlet s = \"\nmod helper;\";\nlet r = r#\"\nmod \"helper\";\"#;\nlet c = '\"'; // mod helper;\nfn f<'a>(s: &'a str) {}\n";
+    assert_eq!(arp(untouched), untouched);
+}
+
+#[test]
+fn test_blank_comments_and_literals() {
+    assert_eq!(
+        blank_comments_and_literals("a // b\nc /* d\ne */ f \"g\\\"h\" 'i' '\\'' 'j br\"k\" é"),
+        "a     \nc     \n     f \"    \" ' ' '  ' 'j br\" \" é"
+    );
+}
+
+/**
+Settings for how `rust-script` should build and run a script, given in the `[package.metadata.rustscript]` table of its manifest.
+
+These act as defaults; options given on the command line take precedence.
+*/
+#[derive(Debug, Default, PartialEq)]
+pub struct ScriptSettings {
+    /// Which toolchain to build the script with.
+    pub toolchain: Option<String>,
+    /// If the script should be built in debug mode.
+    pub debug: Option<bool>,
+    /// Wrapper to run the built binary with.
+    pub wrapper: Option<String>,
+    /// Cargo features to enable.
+    pub features: Vec<String>,
+    /// Arguments to pass to the script if none are given.
+    pub args: Vec<String>,
+    /// Environment variables to set for the script, unless they are already set.
+    pub env: Vec<(String, String)>,
+    /// If the toolchain should be taken from a `rust-toolchain.toml` near the script.
+    pub toolchain_file: Option<bool>,
+    /// If the script should keep a lockfile next to it.
+    pub lockfile: Option<bool>,
+}
+
+impl ScriptSettings {
+    fn from_manifest(mani: &toml::value::Table) -> MainResult<Self> {
+        let mut settings = Self::default();
+
+        let table = mani
+            .get("package")
+            .and_then(|v| v.get("metadata"))
+            .and_then(|v| v.get("rustscript"));
+        let table = match table {
+            None => return Ok(settings),
+            Some(toml::Value::Table(table)) => table,
+            Some(_) => return Err("`package.metadata.rustscript` must be a table".into()),
+        };
+
+        fn invalid(key: &str, expected: &str) -> MainError {
+            format!(
+                "invalid `package.metadata.rustscript.{}`: expected {}",
+                key, expected
+            )
+            .into()
+        }
+
+        fn string_array(key: &str, value: &toml::Value) -> MainResult<Vec<String>> {
+            value
+                .as_array()
+                .and_then(|a| {
+                    a.iter()
+                        .map(|v| v.as_str().map(Into::into))
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| invalid(key, "an array of strings"))
+        }
+
+        for (key, value) in table {
+            match key.as_str() {
+                "toolchain" => {
+                    settings.toolchain = Some(
+                        value
+                            .as_str()
+                            .ok_or_else(|| invalid(key, "a string"))?
+                            .into(),
+                    );
+                }
+                "debug" => {
+                    settings.debug =
+                        Some(value.as_bool().ok_or_else(|| invalid(key, "a boolean"))?);
+                }
+                "wrapper" => {
+                    settings.wrapper = Some(
+                        value
+                            .as_str()
+                            .ok_or_else(|| invalid(key, "a string"))?
+                            .into(),
+                    );
+                }
+                "features" => settings.features = string_array(key, value)?,
+                "args" => settings.args = string_array(key, value)?,
+                "env" => {
+                    let env = value
+                        .as_table()
+                        .ok_or_else(|| invalid(key, "a table of strings"))?;
+                    for (name, value) in env {
+                        let value = value
+                            .as_str()
+                            .ok_or_else(|| invalid(key, "a table of strings"))?;
+                        settings.env.push((name.clone(), value.into()));
+                    }
+                }
+                "toolchain-file" => {
+                    settings.toolchain_file =
+                        Some(value.as_bool().ok_or_else(|| invalid(key, "a boolean"))?);
+                }
+                "lockfile" => {
+                    settings.lockfile =
+                        Some(value.as_bool().ok_or_else(|| invalid(key, "a boolean"))?);
+                }
+                _ => {
+                    // Leave room for settings from newer versions.
+                    info!("ignoring unknown setting `{}`", key);
+                }
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+#[test]
+fn test_script_settings() {
+    macro_rules! ss {
+        ($m:expr) => {
+            ScriptSettings::from_manifest(&toml::from_str($m).unwrap()).map_err(|e| e.to_string())
+        };
+    }
+
+    assert_eq!(ss!(""), Ok(ScriptSettings::default()));
+
+    assert_eq!(
+        ss!(r#"
+[package.metadata.rustscript]
+toolchain = "nightly"
+debug = true
+wrapper = "hyperfine --runs 3"
+features = ["a", "b"]
+args = ["--verbose"]
+env = { RUST_LOG = "debug" }
+toolchain-file = true
+lockfile = true
+"#),
+        Ok(ScriptSettings {
+            toolchain: Some("nightly".into()),
+            debug: Some(true),
+            wrapper: Some("hyperfine --runs 3".into()),
+            features: vec!["a".into(), "b".into()],
+            args: vec!["--verbose".into()],
+            env: vec![("RUST_LOG".into(), "debug".into())],
+            toolchain_file: Some(true),
+            lockfile: Some(true),
+        })
+    );
+
+    assert_eq!(
+        ss!(r#"
+[package.metadata.rustscript]
+debug = "yes"
+"#),
+        Err("invalid `package.metadata.rustscript.debug`: expected a boolean".into())
+    );
+
+    assert_eq!(
+        ss!(r#"
+[package.metadata.rustscript]
+args = "--verbose"
+"#),
+        Err("invalid `package.metadata.rustscript.args`: expected an array of strings".into())
+    );
 }
 
 #[cfg(test)]
@@ -146,8 +765,11 @@ fn test_split_input() {
                 &bin_name,
                 &script_name,
                 toolchain.clone(),
+                WildcardPolicy::Allow,
+                None,
             )
             .ok()
+            .map(|(m, p, s, _, _, _)| (m, p, s))
         };
     }
 
@@ -247,9 +869,12 @@ version = "0.1.0""#,
             "",
             &bin_name,
             "main.rs",
-            Some("stable".to_string())
+            Some("stable".to_string()),
+            WildcardPolicy::Allow,
+            None,
         )
-        .ok(),
+        .ok()
+        .map(|(m, p, s, _, _, _)| (m, p, s)),
         r!(
             format!(
                 "{}{}",
@@ -405,6 +1030,37 @@ path = "/dummy/main.rs"
 [dependencies]
 time = "0.1.25"
 
+[package]
+authors = ["Anonymous"]
+edition = "2021"
+name = "binary-name"
+version = "0.1.0""#,
+                STRIP_SECTION
+            ),
+            "/dummy/main.rs",
+            None
+        )
+    );
+
+    assert_eq!(
+        si!(f(r#"
+//! ```cargo
+//! [[bin]]
+//! name = "binary-name"
+//! test = false
+//! ```
+fn main() {}
+"#)),
+        r!(
+            format!(
+                "{}{}",
+                r#"[[bin]]
+name = "binary-name"
+path = "/dummy/main.rs"
+test = false
+
+[dependencies]
+
 [package]
 authors = ["Anonymous"]
 edition = "2021"
@@ -419,7 +1075,12 @@ version = "0.1.0""#,
 
     assert_eq!(
         si!(f(r#"#!/usr/bin/env rust-script
-println!("Hello")"#)),
+---cargo
+[dependencies]
+time = "0.1.25"
+---
+fn main() {}
+"#)),
         r!(
             format!(
                 "{}{}",
@@ -428,6 +1089,7 @@ name = "binary-name"
 path = "main.rs"
 
 [dependencies]
+time = "0.1.25"
 
 [package]
 authors = ["Anonymous"]
@@ -437,88 +1099,970 @@ version = "0.1.0""#,
                 STRIP_SECTION
             ),
             "/package/main.rs",
-            Some(
-                r#"
-fn main() -> Result<(), Box<dyn std::error::Error+Sync+Send>> {
-    {println!("Hello")}
-    Ok(())
-}
-"#
-                .to_string()
-            )
+            Some("\n\n\n\n\nfn main() {}\n".to_string())
         )
     );
-}
-
-/**
-Returns a slice of the input string with the leading shebang, if there is one, omitted.
-*/
-fn strip_shebang(s: &str) -> &str {
-    let re_shebang: Regex = Regex::new(r"^#![^\[].*?(\r\n|\n)").unwrap();
-    match re_shebang.find(s) {
-        Some(m) => &s[m.end()..],
-        None => s,
-    }
-}
 
-/**
-Represents the kind, and content of, an embedded manifest.
-*/
-#[derive(Debug, Eq, PartialEq)]
-enum Manifest<'s> {
-    /// The manifest is a valid TOML fragment.
-    Toml(&'s str),
-    /// The manifest is a valid TOML fragment (owned).
-    // TODO: Change to Cow<'s, str>.
-    TomlOwned(String),
-    /// The manifest is a comma-delimited list of dependencies.
-    DepList(&'s str),
-}
+    assert_eq!(
+        split_input(
+            &f(r#"
+// cargo-deps: time="0.1.25"
+fn main() {}
+"#),
+            &PathBuf::from("/dummy"),
+            &[
+                ("libc".to_string(), "0.2.5".to_string()),
+                ("time".to_string(), "*".to_string())
+            ],
+            &[],
+            "/package",
+            &bin_name,
+            &script_name,
+            None,
+            WildcardPolicy::Allow,
+            None,
+        )
+        .ok()
+        .map(|(m, p, s, _, _, _)| (m, p, s)),
+        r!(
+            format!(
+                "{}{}",
+                r#"[[bin]]
+name = "binary-name"
+path = "/dummy/main.rs"
 
-impl Manifest<'_> {
-    pub fn into_toml(self) -> MainResult<toml::value::Table> {
-        use self::Manifest::*;
-        match self {
-            Toml(s) => toml::from_str(s),
-            TomlOwned(ref s) => toml::from_str(s),
-            DepList(s) => Manifest::dep_list_to_toml(s),
-        }
-        .map_err(|e| {
-            MainError::Tag(
-                "could not parse embedded manifest".into(),
-                Box::new(MainError::Other(Box::new(e))),
-            )
-        })
-    }
+[dependencies]
+libc = "0.2.5"
+time = "0.1.25"
 
-    fn dep_list_to_toml(s: &str) -> ::std::result::Result<toml::value::Table, toml::de::Error> {
-        let mut r = String::new();
-        r.push_str("[dependencies]\n");
-        for dep in s.trim().split(',') {
-            // If there's no version specified, add one.
-            match dep.contains('=') {
-                true => {
-                    r.push_str(dep);
-                    r.push('\n');
+[package]
+authors = ["Anonymous"]
+edition = "2021"
+name = "binary-name"
+version = "0.1.0""#,
+                STRIP_SECTION
+            ),
+            "/dummy/main.rs",
+            None
+        )
+    );
+
+    assert!(split_input(
+        &f(r#"
+// cargo-deps: time="0.1.25"
+fn main() {}
+"#),
+        &PathBuf::from("/dummy"),
+        &[("time".to_string(), "0.1.38".to_string())],
+        &[],
+        "/package",
+        &bin_name,
+        &script_name,
+        None,
+        WildcardPolicy::Allow,
+        None,
+    )
+    .is_err());
+
+    assert_eq!(
+        si!(f(r#"#!/usr/bin/env rust-script
+println!("Hello")"#)),
+        r!(
+            format!(
+                "{}{}",
+                r#"[[bin]]
+name = "binary-name"
+path = "main.rs"
+
+[dependencies]
+
+[package]
+authors = ["Anonymous"]
+edition = "2021"
+name = "binary-name"
+version = "0.1.0""#,
+                STRIP_SECTION
+            ),
+            "/package/main.rs",
+            Some(
+                r#"
+fn main() -> Result<(), Box<dyn std::error::Error+Sync+Send>> {
+    {println!("Hello")}
+    Ok(())
+}
+"#
+                .to_string()
+            )
+        )
+    );
+}
+
+/**
+Returns a slice of the input string with the leading shebang, if there is one, omitted.
+*/
+pub fn strip_shebang(s: &str) -> &str {
+    let re_shebang: Regex = Regex::new(r"^#![^\[].*?(\r\n|\n)").unwrap();
+    match re_shebang.find(s) {
+        Some(m) => &s[m.end()..],
+        None => s,
+    }
+}
+
+/**
+Represents the kind, and content of, an embedded manifest.
+*/
+#[derive(Debug, Eq, PartialEq)]
+pub enum Manifest<'s> {
+    /// The manifest is a valid TOML fragment.
+    Toml(&'s str),
+    /// The manifest is a valid TOML fragment (owned), along with where its pieces came from.
+    // TODO: Change to Cow<'s, str>.
+    TomlOwned(String, Origins<'s>),
+    /// The manifest is a comma-delimited list of dependencies, from one or more lines.
+    DepList(Vec<&'s str>),
+    /// The manifest is a TOML fragment from a `---cargo` frontmatter block.
+    Frontmatter(&'s str),
+}
+
+/**
+Records where the pieces of a manifest that had to be pieced together came from.
+
+Each entry pairs an offset into the manifest with the slice of the script that the manifest text at that offset was copied from.
+*/
+pub type Origins<'s> = Vec<(usize, &'s str)>;
+
+impl Manifest<'_> {
+    /// Describes where in the script the manifest is, for messages.
+    pub fn form_name(&self) -> &'static str {
+        match self {
+            Manifest::Toml(_) => "the manifest",
+            Manifest::TomlOwned(..) => "the code block",
+            Manifest::DepList(_) => "the short comment",
+            Manifest::Frontmatter(_) => "the frontmatter",
+        }
+    }
+
+    /**
+    Parses the manifest.
+
+    `script` is the full text of the script the manifest was found in, and `name` is what to call it in error messages.
+    */
+    pub fn into_toml(self, script: &str, name: &str) -> MainResult<toml::value::Table> {
+        use self::Manifest::*;
+        let (toml, origins) = match self {
+            Toml(s) | Frontmatter(s) => (s.into(), vec![(0, s)]),
+            TomlOwned(s, origins) => (s, origins),
+            DepList(lines) => match dep_list_to_toml(&lines) {
+                Ok(r) => r,
+                Err((msg, piece)) => {
+                    let location = locate_in_script(0, &vec![(0, piece)], script);
+                    return Err(match location {
+                        Some(offset) => format!(
+                            "could not parse embedded manifest: {}\n{}",
+                            msg,
+                            render_snippet(script, offset, name)
+                        ),
+                        None => format!("could not parse embedded manifest: {}", msg),
+                    }
+                    .into());
+                }
+            },
+        };
+
+        toml::from_str(&toml).map_err(|e: toml::de::Error| {
+            let location = e
+                .span()
+                .and_then(|span| locate_in_script(span.start, &origins, script));
+            match location {
+                Some(offset) => format!(
+                    "could not parse embedded manifest: {}\n{}",
+                    e.message().trim_end(),
+                    render_snippet(script, offset, name)
+                )
+                .into(),
+                None => MainError::Tag(
+                    "could not parse embedded manifest".into(),
+                    Box::new(MainError::Other(Box::new(e))),
+                ),
+            }
+        })
+    }
+}
+
+/**
+Turns the lines of a short comment manifest into a `[dependencies]` table.
+
+Each line is a comma-delimited list of dependencies, each of which looks like one of:
+
+- `name`, for any version.
+- `name=version`, where the version can be quoted or not.
+- `name={ ... }`, with a TOML inline table.
+- `name(feature, key=value, ...)`, optionally followed by `=version`, with features to enable and other keys of the dependency (*e.g.* `path`, `git`, `optional`).
+
+On failure, returns a message along with the part of the line that is at fault.
+*/
+fn dep_list_to_toml<'s>(lines: &[&'s str]) -> Result<(String, Origins<'s>), (String, &'s str)> {
+    let mut r = String::new();
+    let mut origins = Vec::new();
+    r.push_str("[dependencies]\n");
+
+    for line in lines {
+        origins.push((r.len(), *line));
+        for dep in parse_dep_list_line(line)? {
+            origins.push((r.len(), dep.name));
+            r.push_str(dep.name);
+            r.push_str(" = ");
+
+            if dep.args.is_empty() {
+                match dep.value {
+                    None => r.push_str("\"*\""),
+                    Some(DepListValue::Table(table)) => {
+                        origins.push((r.len(), table));
+                        r.push_str(table);
+                    }
+                    Some(DepListValue::Version(version)) => {
+                        origins.push((r.len(), version));
+                        push_quoted(&mut r, version);
+                    }
+                }
+                r.push('\n');
+                continue;
+            }
+
+            let mut features = Vec::new();
+            let mut keys = Vec::new();
+            for arg in &dep.args {
+                match arg {
+                    DepListArg::Feature(feature) => features.push(*feature),
+                    DepListArg::Key(key, value) => keys.push((*key, *value)),
+                }
+            }
+
+            let has_source = keys
+                .iter()
+                .any(|(k, _)| matches!(*k, "path" | "git" | "registry"));
+            let version = match dep.value {
+                Some(DepListValue::Table(table)) => {
+                    return Err((
+                        "cannot give both `(...)` and a table for a dependency".into(),
+                        table,
+                    ));
+                }
+                Some(DepListValue::Version(version)) => {
+                    if let Some((key, _)) = keys.iter().find(|(k, _)| *k == "version") {
+                        return Err(("version given twice".into(), *key));
+                    }
+                    Some(version)
+                }
+                None if has_source || keys.iter().any(|(k, _)| *k == "version") => None,
+                None => Some("*"),
+            };
+
+            r.push_str("{ ");
+            let mut first = true;
+            let mut sep = |r: &mut String| {
+                if !first {
+                    r.push_str(", ");
+                }
+                first = false;
+            };
+            if let Some(version) = version {
+                sep(&mut r);
+                r.push_str("version = ");
+                origins.push((r.len(), version));
+                push_quoted(&mut r, version);
+            }
+            for (key, value) in keys {
+                sep(&mut r);
+                r.push_str(key);
+                r.push_str(" = ");
+                origins.push((r.len(), value));
+                match key {
+                    "optional" | "default-features" => r.push_str(value),
+                    _ => push_quoted(&mut r, value),
+                }
+            }
+            if !features.is_empty() {
+                sep(&mut r);
+                r.push_str("features = [");
+                for (i, feature) in features.iter().enumerate() {
+                    if i > 0 {
+                        r.push_str(", ");
+                    }
+                    origins.push((r.len(), *feature));
+                    push_quoted(&mut r, feature);
+                }
+                r.push(']');
+            }
+            r.push_str(" }\n");
+        }
+    }
+
+    return Ok((r, origins));
+
+    /// Pushes a value as a TOML string, unless it's already quoted.
+    fn push_quoted(r: &mut String, value: &str) {
+        if value.starts_with('"') || value.starts_with('\'') {
+            r.push_str(value);
+        } else {
+            r.push_str(&toml::Value::String(value.into()).to_string());
+        }
+    }
+}
+
+#[test]
+fn test_dep_list_to_toml() {
+    macro_rules! dl {
+        ($($l:expr),*) => {
+            dep_list_to_toml(&[$($l),*])
+                .map(|(toml, _)| toml)
+                .map_err(|(msg, at)| (msg, at.to_string()))
+        };
+    }
+    macro_rules! ok {
+        ($t:expr) => {
+            Ok(format!("[dependencies]\n{}", $t))
+        };
+    }
+    macro_rules! err {
+        ($m:expr, $at:expr) => {
+            Err(($m.to_string(), $at.to_string()))
+        };
+    }
+
+    assert_eq!(dl!(" time"), ok!("time = \"*\"\n"));
+    assert_eq!(dl!(r#" time="0.1.25""#), ok!("time = \"0.1.25\"\n"));
+    assert_eq!(dl!(" time=0.1.25"), ok!("time = \"0.1.25\"\n"));
+    assert_eq!(
+        dl!(r#" time="0.1.25", libc="0.2.5""#),
+        ok!("time = \"0.1.25\"\nlibc = \"0.2.5\"\n")
+    );
+    assert_eq!(
+        dl!(r#" time="0.1.25","#, r#" libc = "0.2.5""#),
+        ok!("time = \"0.1.25\"\nlibc = \"0.2.5\"\n")
+    );
+    assert_eq!(
+        dl!(r#" time=">=0.1, <0.2""#),
+        ok!("time = \">=0.1, <0.2\"\n")
+    );
+    assert_eq!(
+        dl!(r#" serde(derive, rc)="1""#),
+        ok!("serde = { version = \"1\", features = [\"derive\", \"rc\"] }\n")
+    );
+    assert_eq!(
+        dl!(" serde(derive)"),
+        ok!("serde = { version = \"*\", features = [\"derive\"] }\n")
+    );
+    assert_eq!(
+        dl!(r#" mylib(path="../mylib")"#),
+        ok!("mylib = { path = \"../mylib\" }\n")
+    );
+    assert_eq!(
+        dl!(r#" mylib(fancy, git="https://example.com/mylib.git", rev=abc123, default-features=false)"#),
+        ok!("mylib = { git = \"https://example.com/mylib.git\", rev = \"abc123\", default-features = false, features = [\"fancy\"] }\n")
+    );
+    assert_eq!(
+        dl!(r#" time={ version = "0.1.25", features = ["a", "b"] }"#),
+        ok!("time = { version = \"0.1.25\", features = [\"a\", \"b\"] }\n")
+    );
+
+    assert_eq!(
+        dl!(r#" time="0.1.25" libc"#),
+        err!("expected `,` between dependencies, found `l`", "libc")
+    );
+    assert_eq!(
+        dl!(r#" time, ="1""#),
+        err!("expected a package name, found `=`", r#"="1""#)
+    );
+    assert_eq!(
+        dl!(r#" time="0.1.25"#),
+        err!("unterminated string", r#""0.1.25"#)
+    );
+    assert_eq!(
+        dl!(r#" time="latest""#),
+        err!("`latest` is not a valid version requirement", r#""latest""#)
+    );
+    assert_eq!(
+        dl!(" serde(derive"),
+        err!("expected `)`, found end of line", "")
+    );
+    assert_eq!(
+        dl!(" serde(derive rc)"),
+        err!("expected `,` or `)`, found `r`", "rc)")
+    );
+    assert_eq!(
+        dl!(" serde(feature=derive)"),
+        err!("unknown dependency key `feature`, expected one of branch, default-features, git, optional, package, path, registry, rev, script, tag, version", "feature")
+    );
+    assert_eq!(
+        dl!(" serde(optional=yes)"),
+        err!("`optional` must be `true` or `false`", "yes")
+    );
+    assert_eq!(
+        dl!(r#" serde(version="1")="2""#),
+        err!("version given twice", "version")
+    );
+    assert_eq!(
+        dl!(r#" serde(derive)={ version = "1" }"#),
+        err!(
+            "cannot give both `(...)` and a table for a dependency",
+            r#"{ version = "1" }"#
+        )
+    );
+}
+
+/**
+Parses one line of a short comment manifest, without checking that the dependencies make sense as a whole.
+*/
+pub fn parse_dep_list_line(line: &str) -> Result<Vec<DepListEntry<'_>>, (String, &str)> {
+    DepListParser { line, pos: 0 }.parse()
+}
+
+/// A dependency in a short comment manifest.
+pub struct DepListEntry<'s> {
+    pub name: &'s str,
+    pub args: Vec<DepListArg<'s>>,
+    pub value: Option<DepListValue<'s>>,
+    /// All of the entry, from the name to the end of its value.
+    pub span: &'s str,
+}
+
+/// An argument in the parentheses following a dependency name.
+pub enum DepListArg<'s> {
+    Feature(&'s str),
+    Key(&'s str, &'s str),
+}
+
+/// The value following `=` in a short comment manifest.
+pub enum DepListValue<'s> {
+    Version(&'s str),
+    Table(&'s str),
+}
+
+/// Keys which may be given in the parentheses following a dependency name.
+const DEP_LIST_KEYS: &[&str] = &[
+    "branch",
+    "default-features",
+    "git",
+    "optional",
+    "package",
+    "path",
+    "registry",
+    "rev",
+    "script",
+    "tag",
+    "version",
+];
+
+/// A hand-written parser for one line of a short comment manifest.
+struct DepListParser<'s> {
+    line: &'s str,
+    pos: usize,
+}
+
+impl<'s> DepListParser<'s> {
+    fn parse(&mut self) -> Result<Vec<DepListEntry<'s>>, (String, &'s str)> {
+        let mut deps = Vec::new();
+        loop {
+            self.skip_space();
+            if self.at_end() {
+                break;
+            }
+
+            let start = self.pos;
+            let name = self.ident();
+            if name.is_empty() {
+                return Err(self.error("expected a package name"));
+            }
+
+            self.skip_space();
+            let mut args = Vec::new();
+            if self.eat('(') {
+                loop {
+                    self.skip_space();
+                    if self.eat(')') {
+                        break;
+                    }
+                    let arg = self.ident();
+                    if arg.is_empty() {
+                        return Err(self.error("expected a feature or `key=value`"));
+                    }
+                    self.skip_space();
+                    if self.eat('=') {
+                        if !DEP_LIST_KEYS.contains(&arg) {
+                            return Err((
+                                format!(
+                                    "unknown dependency key `{}`, expected one of {}",
+                                    arg,
+                                    DEP_LIST_KEYS.join(", ")
+                                ),
+                                arg,
+                            ));
+                        }
+                        self.skip_space();
+                        let value = self.scalar()?;
+                        if matches!(arg, "optional" | "default-features")
+                            && !matches!(value, "true" | "false")
+                        {
+                            return Err((format!("`{}` must be `true` or `false`", arg), value));
+                        }
+                        if arg == "version" {
+                            check_version(value)?;
+                        }
+                        args.push(DepListArg::Key(arg, value));
+                    } else {
+                        args.push(DepListArg::Feature(arg));
+                    }
+                    self.skip_space();
+                    if !self.eat(',') && !self.peek_is(')') {
+                        if self.at_end() {
+                            return Err(self.error("expected `)`"));
+                        }
+                        return Err(self.error("expected `,` or `)`"));
+                    }
+                }
+                self.skip_space();
+            }
+
+            let value = if self.eat('=') {
+                self.skip_space();
+                if self.peek_is('{') {
+                    Some(DepListValue::Table(self.table()?))
+                } else {
+                    let version = self.scalar()?;
+                    check_version(version)?;
+                    Some(DepListValue::Version(version))
+                }
+            } else {
+                None
+            };
+
+            let span = self.line[start..self.pos].trim_end();
+            deps.push(DepListEntry {
+                name,
+                args,
+                value,
+                span,
+            });
+
+            self.skip_space();
+            if !self.eat(',') && !self.at_end() {
+                return Err(self.error("expected `,` between dependencies"));
+            }
+        }
+        return Ok(deps);
+
+        fn check_version(version: &str) -> Result<(), (String, &str)> {
+            let unquoted = version.trim_matches(|c| c == '"' || c == '\'');
+            if crate::dependency::is_valid_version_req(unquoted) {
+                Ok(())
+            } else {
+                Err((
+                    format!("`{}` is not a valid version requirement", unquoted),
+                    version,
+                ))
+            }
+        }
+    }
+
+    fn rest(&self) -> &'s str {
+        &self.line[self.pos..]
+    }
+
+    fn at_end(&self) -> bool {
+        self.rest().is_empty()
+    }
+
+    fn peek_is(&self, c: char) -> bool {
+        self.rest().starts_with(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek_is(c);
+        if found {
+            self.pos += c.len_utf8();
+        }
+        found
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'s str {
+        let rest = self.rest();
+        let len = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn ident(&mut self) -> &'s str {
+        self.take_while(|c| c.is_alphanumeric() || "_-+./".contains(c))
+    }
+
+    /// Takes a quoted string (quotes included), or a bare word.
+    fn scalar(&mut self) -> Result<&'s str, (String, &'s str)> {
+        let rest = self.rest();
+        match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let mut escaped = false;
+                for (i, c) in rest.char_indices().skip(1) {
+                    match c {
+                        '\\' if quote == '"' && !escaped => escaped = true,
+                        c if c == quote && !escaped => {
+                            self.pos += i + 1;
+                            return Ok(&rest[..i + 1]);
+                        }
+                        _ => escaped = false,
+                    }
                 }
-                false => {
-                    r.push_str(dep);
-                    r.push_str("=\"*\"\n");
+                Err(("unterminated string".into(), rest))
+            }
+            _ => {
+                let word = self.take_while(|c| !c.is_whitespace() && !",()".contains(c));
+                if word.is_empty() {
+                    Err(self.error("expected a value"))
+                } else {
+                    Ok(word)
                 }
             }
         }
+    }
 
-        toml::from_str(&r)
+    /// Takes a TOML inline table, which is checked by the TOML parser later.
+    fn table(&mut self) -> Result<&'s str, (String, &'s str)> {
+        let rest = self.rest();
+        let mut depth = 0;
+        let mut quote = None;
+        for (i, c) in rest.char_indices() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '{' | '[') => depth += 1,
+                (None, '}' | ']') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += i + 1;
+                        return Ok(&rest[..i + 1]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(("unterminated table".into(), rest))
+    }
+
+    fn error(&self, msg: &str) -> (String, &'s str) {
+        let rest = self.rest();
+        let found = match rest.chars().next() {
+            Some(c) => format!("`{}`", c),
+            None => "end of line".into(),
+        };
+        (format!("{}, found {}", msg, found), rest)
     }
 }
 
+/**
+Maps an offset into a manifest back to an offset into the script it was extracted from.
+*/
+fn locate_in_script(offset: usize, origins: &Origins, script: &str) -> Option<usize> {
+    let (start, piece) = origins.iter().rev().find(|(start, _)| *start <= offset)?;
+    let piece_offset = offset_of(script, piece)?;
+    // Anything past the end of the piece was made up (a newline, or a default version), so point at the end of it.
+    Some(piece_offset + (offset - start).min(piece.len()))
+}
+
+/**
+Returns the offset of `piece` in `s`, or `None` if it isn't a slice of `s`.
+*/
+pub fn offset_of(s: &str, piece: &str) -> Option<usize> {
+    let offset = (piece.as_ptr() as usize).checked_sub(s.as_ptr() as usize)?;
+    if offset + piece.len() > s.len() {
+        return None;
+    }
+    Some(offset)
+}
+
+#[test]
+fn test_offset_of() {
+    let s = String::from("[dependencies]\ntime = \"0.1\"\n");
+    assert_eq!(offset_of(&s, &s[15..19]), Some(15));
+    assert_eq!(offset_of(&s, &s[s.len()..]), Some(s.len()));
+    // Neither a static string nor a copy is a slice of the script.
+    assert_eq!(offset_of(&s, ""), None);
+    assert_eq!(offset_of(&s, &s.clone()[15..19]), None);
+}
+
+/**
+Renders a `path:line:column` location along with the offending line and a caret pointing at the given offset.
+*/
+fn render_snippet(script: &str, offset: usize, name: &str) -> String {
+    let line_start = script[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line_end = script[offset..]
+        .find('\n')
+        .map(|i| offset + i)
+        .unwrap_or(script.len());
+    let line = script[line_start..line_end].trim_end_matches('\r');
+    let line_no = script[..line_start].matches('\n').count() + 1;
+    let column = script[line_start..offset].chars().count() + 1;
+
+    let gutter = " ".repeat(line_no.to_string().len());
+    let caret_indent: String = script[line_start..offset]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    format!(
+        "{gutter}--> {name}:{line_no}:{column}\n\
+         {gutter} |\n\
+         {line_no} | {line}\n\
+         {gutter} | {caret_indent}^"
+    )
+}
+
+#[test]
+fn test_manifest_errors() {
+    let script = r#"#!/usr/bin/env rust-script
+//! A script.
+//!
+//! ```cargo
+//! [dependencies]
+//! time = "0.1.25
+//! ```
+fn main() {}
+"#;
+    let (manifest, _) = find_embedded_manifest(strip_shebang(script)).unwrap();
+    assert_eq!(
+        manifest
+            .into_toml(script, "script.rs")
+            .map_err(|e| e.to_string()),
+        Err(
+            r#"could not parse embedded manifest: invalid basic string, expected `"`
+ --> script.rs:6:19
+  |
+6 | //! time = "0.1.25
+  |                   ^"#
+                .into()
+        )
+    );
+
+    let script = r#"/*!
+ * ```cargo
+ * [dependencies]
+ * time = 0.1.25
+ * ```
+ */
+fn main() {}
+"#;
+    let (manifest, _) = find_embedded_manifest(script).unwrap();
+    assert_eq!(
+        manifest
+            .into_toml(script, "script.rs")
+            .map_err(|e| e.to_string()),
+        Err(
+            r#"could not parse embedded manifest: invalid float, expected nothing
+ --> script.rs:4:14
+  |
+4 |  * time = 0.1.25
+  |              ^"#
+                .into()
+        )
+    );
+
+    let script = r#"// cargo-deps: time="0.1.25"
+// cargo-deps: libc="0.2.5" serde
+fn main() {}
+"#;
+    let (manifest, _) = find_embedded_manifest(script).unwrap();
+    assert_eq!(
+        manifest
+            .into_toml(script, "script.rs")
+            .map_err(|e| e.to_string()),
+        Err(
+            r#"could not parse embedded manifest: expected `,` between dependencies, found `s`
+ --> script.rs:2:29
+  |
+2 | // cargo-deps: libc="0.2.5" serde
+  |                             ^"#
+                .into()
+        )
+    );
+
+    let script = r#"// cargo-deps: libc={ version = 0.2.5 }
+fn main() {}
+"#;
+    let (manifest, _) = find_embedded_manifest(script).unwrap();
+    assert_eq!(
+        manifest
+            .into_toml(script, "script.rs")
+            .map_err(|e| e.to_string()),
+        Err(
+            r#"could not parse embedded manifest: invalid float, expected nothing
+ --> script.rs:1:36
+  |
+1 | // cargo-deps: libc={ version = 0.2.5 }
+  |                                    ^"#
+                .into()
+        )
+    );
+
+    let script = r#"---
+[dependencies]
+time = { version = "0.1.25"
+---
+fn main() {}
+"#;
+    let (manifest, _) = find_embedded_manifest(script).unwrap();
+    assert_eq!(
+        manifest
+            .into_toml(script, "script.rs")
+            .map_err(|e| e.to_string()),
+        Err(
+            r#"could not parse embedded manifest: unclosed inline table, expected `}`
+ --> script.rs:3:28
+  |
+3 | time = { version = "0.1.25"
+  |                            ^"#
+                .into()
+        )
+    );
+}
+
+/**
+Returns the script's source without its shebang and embedded manifest, for use outside of `rust-script`.
+
+Short comment manifests and `cargo` code blocks are cut out line by line, along with the blank doc comment lines leading up to a code block.
+*/
+pub fn strip_embedded_manifest(s: &str) -> String {
+    let s = strip_shebang(s);
+    let (slices, is_code_block) = match find_embedded_manifest(s) {
+        Some((Manifest::Frontmatter(_), source)) => {
+            return source.trim_start_matches(['\r', '\n']).into()
+        }
+        Some((Manifest::DepList(lines), _)) => (lines, false),
+        Some((Manifest::TomlOwned(_, origins), _)) => {
+            (origins.into_iter().map(|(_, slice)| slice).collect(), true)
+        }
+        Some((Manifest::Toml(_), _)) | None => return s.into(),
+    };
+
+    let lines: Vec<&str> = s.split_inclusive('\n').collect();
+    let line_of = |slice: &str| {
+        let offset = offset_of(s, slice)?;
+        let mut line_start = 0;
+        let line = lines
+            .iter()
+            .position(|line| {
+                line_start += line.len();
+                offset < line_start
+            })
+            .unwrap_or(lines.len() - 1);
+        Some(line)
+    };
+    let mut remove: Vec<bool> = vec![false; lines.len()];
+
+    if is_code_block {
+        let (first, last) = match (
+            slices.first().and_then(|first| line_of(first)),
+            slices.last().and_then(|last| line_of(last)),
+        ) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return s.into(),
+        };
+        let is_fence = |i: usize| lines[i].contains("```") || lines[i].contains("~~~");
+        let mut first = if first > 0 && is_fence(first - 1) {
+            first - 1
+        } else {
+            first
+        };
+        let last = if last + 1 < lines.len() && is_fence(last + 1) {
+            last + 1
+        } else {
+            last
+        };
+        let re_blank_doc: Regex = Regex::new(r"^\s*//!\s*$").unwrap();
+        while first > 0 && re_blank_doc.is_match(lines[first - 1]) {
+            first -= 1;
+        }
+        for r in &mut remove[first..=last] {
+            *r = true;
+        }
+    } else {
+        for line in slices.into_iter().filter_map(line_of) {
+            remove[line] = true;
+        }
+    }
+
+    let source: String = lines
+        .iter()
+        .zip(remove)
+        .filter(|(_, remove)| !remove)
+        .map(|(line, _)| *line)
+        .collect();
+    source.trim_start_matches(['\r', '\n']).into()
+}
+
+#[test]
+fn test_strip_embedded_manifest() {
+    let sem = strip_embedded_manifest;
+
+    assert_eq!(sem("fn main() {}\n"), "fn main() {}\n");
+    assert_eq!(
+        sem("#!/usr/bin/env rust-script\nfn main() {}\n"),
+        "fn main() {}\n"
+    );
+    assert_eq!(
+        sem("#!/usr/bin/env rust-script\n---\n[dependencies]\ntime = \"0.1.25\"\n---\n\nfn main() {}\n"),
+        "fn main() {}\n"
+    );
+    assert_eq!(
+        sem("// cargo-deps: time=\"0.1.25\"\n// cargo-deps: libc\n// A comment.\nfn main() {}\n"),
+        "// A comment.\nfn main() {}\n"
+    );
+    assert_eq!(
+        sem(r#"//! ```cargo
+//! [dependencies]
+//! time = "0.1.25"
+//! ```
+fn main() {}
+"#),
+        "fn main() {}\n"
+    );
+    assert_eq!(
+        sem(r#"//! Prints the time.
+//!
+//! ```cargo
+//! [dependencies]
+//! time = "0.1.25"
+//! ```
+fn main() {}
+"#),
+        "//! Prints the time.\nfn main() {}\n"
+    );
+    assert_eq!(
+        sem(r#"/*!
+```cargo
+[dependencies]
+time = "0.1.25"
+```
+*/
+fn main() {}
+"#),
+        "/*!\n*/\nfn main() {}\n"
+    );
+}
+
 /**
 Locates a manifest embedded in Rust source.
 
-Returns `Some((manifest, source))` if it finds a manifest, `None` otherwise.
+Returns `Some((manifest, source))` if it finds a manifest, `None` otherwise.  The returned source has the manifest removed if it isn't valid Rust, which is only the case for frontmatter.
 */
-fn find_embedded_manifest(s: &str) -> Option<(Manifest<'_>, &str)> {
-    find_short_comment_manifest(s).or_else(|| find_code_block_manifest(s))
+pub fn find_embedded_manifest(s: &str) -> Option<(Manifest<'_>, &str)> {
+    find_frontmatter_manifest(s)
+        .or_else(|| find_short_comment_manifest(s))
+        .or_else(|| find_code_block_manifest(s))
 }
 
 #[test]
@@ -580,7 +2124,7 @@ fn main() {
 fn main() {}
 "),
         Some((
-            DepList(" time=\"0.1.25\""),
+            DepList(vec![" time=\"0.1.25\""]),
             "// cargo-deps: time=\"0.1.25\"
 fn main() {}
 "
@@ -592,7 +2136,7 @@ fn main() {}
 fn main() {}
 "),
         Some((
-            DepList(" time=\"0.1.25\", libc=\"0.2.5\""),
+            DepList(vec![" time=\"0.1.25\", libc=\"0.2.5\""]),
             "// cargo-deps: time=\"0.1.25\", libc=\"0.2.5\"
 fn main() {}
 "
@@ -605,7 +2149,7 @@ fn main() {}
 fn main() {}
 "),
         Some((
-            DepList(" time=\"0.1.25\"  "),
+            DepList(vec![" time=\"0.1.25\"  "]),
             "
   // cargo-deps: time=\"0.1.25\"  \n\
 fn main() {}
@@ -613,6 +2157,24 @@ fn main() {}
         ))
     );
 
+    assert_eq!(
+        fem("// cargo-deps: time=\"0.1.25\"
+// cargo-deps: libc=\"0.2.5\"
+// Not a manifest.
+// cargo-deps: serde
+fn main() {}
+"),
+        Some((
+            DepList(vec![" time=\"0.1.25\"", " libc=\"0.2.5\""]),
+            "// cargo-deps: time=\"0.1.25\"
+// cargo-deps: libc=\"0.2.5\"
+// Not a manifest.
+// cargo-deps: serde
+fn main() {}
+"
+        ))
+    );
+
     assert_eq!(
         fem("/* cargo-deps: time=\"0.1.25\" */
 fn main() {}
@@ -640,7 +2202,8 @@ fn main() {}
                 r#"[dependencies]
 time = "0.1.25"
 "#
-                .into()
+                .into(),
+                vec![(0, "[dependencies]"), (15, r#"time = "0.1.25""#)]
             ),
             r#"//! ```Cargo
 //! [dependencies]
@@ -675,7 +2238,8 @@ fn main() {}
                 r#"[dependencies]
 time = "0.1.25"
 "#
-                .into()
+                .into(),
+                vec![(0, "[dependencies]"), (15, r#"time = "0.1.25""#)]
             ),
             r#"/*!
 ```Cargo
@@ -698,6 +2262,78 @@ fn main() {}
         None
     );
 
+    assert_eq!(
+        fem(r#"---cargo
+[dependencies]
+time = "0.1.25"
+---
+fn main() {}
+"#),
+        Some((
+            Frontmatter(
+                r#"[dependencies]
+time = "0.1.25"
+"#
+            ),
+            "fn main() {}
+"
+        ))
+    );
+
+    assert_eq!(
+        fem(r#"
+
+---
+[dependencies]
+time = "0.1.25"
+---
+fn main() {}
+"#),
+        Some((
+            Frontmatter(
+                r#"[dependencies]
+time = "0.1.25"
+"#
+            ),
+            "fn main() {}
+"
+        ))
+    );
+
+    // The closing fence has to match the opening one.
+    assert_eq!(
+        fem(r#"----cargo
+[dependencies]
+time = "0.1.25"
+---
+fn main() {}
+"#),
+        None
+    );
+
+    // Frontmatter for some other tool isn't a manifest.
+    assert_eq!(
+        fem(r#"---toml
+[dependencies]
+time = "0.1.25"
+---
+fn main() {}
+"#),
+        None
+    );
+
+    // Frontmatter must come first.
+    assert_eq!(
+        fem(r#"// A comment.
+---cargo
+[dependencies]
+time = "0.1.25"
+---
+fn main() {}
+"#),
+        None
+    );
+
     assert_eq!(
         fem(r#"/*!
  * ```Cargo
@@ -712,7 +2348,8 @@ fn main() {}
                 r#"[dependencies]
 time = "0.1.25"
 "#
-                .into()
+                .into(),
+                vec![(0, "[dependencies]"), (15, r#"time = "0.1.25""#)]
             ),
             r#"/*!
  * ```Cargo
@@ -727,25 +2364,223 @@ fn main() {}
 }
 
 /**
-Locates a "short comment manifest" in Rust source.
+Locates a "frontmatter manifest" in Rust source, as used by Cargo's single-file packages.
 */
-fn find_short_comment_manifest(s: &str) -> Option<(Manifest<'_>, &str)> {
-    let re: Regex = Regex::new(r"^(?i)\s*//\s*cargo-deps\s*:(.*?)(\r\n|\n)").unwrap();
+fn find_frontmatter_manifest(s: &str) -> Option<(Manifest<'_>, &str)> {
+    let re_open: Regex =
+        Regex::new(r"^(?:[ \t]*(?:\r\n|\n))*(-{3,})[ \t]*([^\r\n]*?)[ \t]*(?:\r\n|\n)").unwrap();
     /*
-    This is pretty simple: the only valid syntax for this is for the first, non-blank line to contain a single-line comment whose first token is `cargo-deps:`.  That's it.
+    The frontmatter must be the first thing in the file, after the shebang and any blank lines.  It is opened by a line of at least three dashes, optionally followed by an info string, and closed by a line with the *same* number of dashes.
+
+    Cargo treats a missing info string as `cargo`, so we do too.  Anything else isn't meant for us.
     */
-    if let Some(cap) = re.captures(s) {
-        if let Some(m) = cap.get(1) {
-            return Some((Manifest::DepList(m.as_str()), s));
+    let cap = re_open.captures(s)?;
+    let fence = cap.get(1)?.as_str();
+    let info = cap.get(2)?.as_str();
+    if !(info.is_empty() || info.eq_ignore_ascii_case("cargo")) {
+        return None;
+    }
+
+    let body_start = cap.get(0)?.end();
+    let mut offset = body_start;
+    for line in s[body_start..].split_inclusive('\n') {
+        if line.trim_end() == fence {
+            let body = &s[body_start..offset];
+            return Some((Manifest::Frontmatter(body), &s[offset + line.len()..]));
         }
+        offset += line.len();
     }
     None
 }
 
+/**
+Locates a "short comment manifest" in Rust source.
+*/
+fn find_short_comment_manifest(s: &str) -> Option<(Manifest<'_>, &str)> {
+    let re_first: Regex = Regex::new(r"^(?i)\s*//\s*cargo-deps\s*:(.*?)(\r\n|\n)").unwrap();
+    let re_next: Regex = Regex::new(r"^(?i)[ \t]*//\s*cargo-deps\s*:(.*?)(\r\n|\n)").unwrap();
+    /*
+    This is pretty simple: the only valid syntax for this is for the first, non-blank line to contain a single-line comment whose first token is `cargo-deps:`.  Any lines of the same form directly following it are part of the manifest, too.  That's it.
+    */
+    let mut lines = Vec::new();
+    let mut rest = s;
+    let mut re = &re_first;
+    while let Some(cap) = re.captures(rest) {
+        lines.push(cap.get(1)?.as_str());
+        rest = &rest[cap.get(0)?.end()..];
+        re = &re_next;
+    }
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some((Manifest::DepList(lines), s))
+    }
+}
+
 /**
 Locates a "code block manifest" in Rust source.
 */
 fn find_code_block_manifest(s: &str) -> Option<(Manifest<'_>, &str)> {
+    let (comment, comment_origins) = find_crate_doc_comment(s)?;
+
+    let (manifest, manifest_offsets) = scrape_markdown_manifest(&comment)?;
+
+    // Chain the offsets in the manifest to the comment, and from there to the script.
+    let origins = manifest_offsets
+        .into_iter()
+        .filter_map(|(manifest_offset, comment_offset)| {
+            let (line_start, line) = comment_origins
+                .iter()
+                .rev()
+                .find(|(line_start, _)| *line_start <= comment_offset)?;
+            // Past the end of the line, or inside a character, the piece is the end of the line, which is still in the script.
+            let piece = line
+                .get(comment_offset - line_start..)
+                .unwrap_or(&line[line.len()..]);
+            Some((manifest_offset, piece))
+        })
+        .collect();
+
+    Some((Manifest::TomlOwned(manifest, origins), s))
+}
+
+/**
+Locates a build script embedded in Rust source, as a fenced code block with the language tokens `rust,build` in the crate's doc comment.
+*/
+pub fn find_embedded_build_script(s: &str) -> Option<String> {
+    let (comment, _) = find_crate_doc_comment(s)?;
+    let (build_script, _) = scrape_markdown_block(&comment, is_build_script_block)?;
+    Some(build_script)
+}
+
+/**
+Locates the build script in a Markdown script, as a fenced code block with the language tokens `rust,build`.
+*/
+pub fn find_markdown_build_script(s: &str) -> Option<String> {
+    let (build_script, _) = scrape_markdown_block(s, is_build_script_block)?;
+    Some(build_script)
+}
+
+fn is_build_script_block(info: &str) -> bool {
+    code_block_tokens(info) == ["rust", "build"]
+}
+
+/**
+Splits the info string of a fenced code block into its lowercased tokens, so ` ```Rust, build` is the same as ` ```rust,build`.
+*/
+fn code_block_tokens(info: &str) -> Vec<String> {
+    info.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/**
+Returns `true` if the script is a Markdown file, to be run as a literate script.
+*/
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("markdown"))
+}
+
+/**
+Splits a Markdown script into its manifest and the Rust source made of its code blocks.
+
+The manifest is the first `cargo` code block.  The source is made of the `rust` code blocks: all of them, or only those tagged `rust,script` if there are any, or the single one named by `block`, as in ` ```rust,setup`.  Blocks tagged `build` or `ignore` are never part of it.
+
+Everything in between is blanked out, so that each line of the source is on the same line as in the Markdown, and compiler messages point at the right line.
+*/
+pub fn split_markdown<'s>(
+    content: &'s str,
+    block: Option<&str>,
+) -> MainResult<(Manifest<'s>, String)> {
+    let manifest = match scrape_markdown_manifest(content) {
+        Some((manifest, offsets)) => {
+            let origins = offsets
+                .into_iter()
+                .map(|(manifest_offset, content_offset)| {
+                    let piece = content
+                        .get(content_offset..)
+                        .unwrap_or(&content[content.len()..]);
+                    (manifest_offset, piece)
+                })
+                .collect();
+            Manifest::TomlOwned(manifest, origins)
+        }
+        None => Manifest::Toml(""),
+    };
+
+    let rust_blocks: Vec<_> = scrape_markdown_code_blocks(content)
+        .into_iter()
+        .map(|(info, texts)| (code_block_tokens(&info), texts))
+        .filter(|(tokens, _)| {
+            tokens.first().map(String::as_str) == Some("rust")
+                && !tokens.iter().any(|t| t == "build" || t == "ignore")
+        })
+        .collect();
+    if rust_blocks.is_empty() {
+        return Err("the Markdown script has no `rust` code blocks".into());
+    }
+
+    let blocks: Vec<_> = match block {
+        Some(name) => {
+            let name = name.to_lowercase();
+            let named: Vec<_> = rust_blocks
+                .into_iter()
+                .filter(|(tokens, _)| tokens[1..].contains(&name))
+                .collect();
+            match named.len() {
+                0 => {
+                    return Err(format!(
+                        "the Markdown script has no `rust` code block named `{}`",
+                        name
+                    )
+                    .into())
+                }
+                1 => named,
+                _ => {
+                    return Err(format!(
+                        "the Markdown script has more than one `rust` code block named `{}`",
+                        name
+                    )
+                    .into())
+                }
+            }
+        }
+        None if rust_blocks
+            .iter()
+            .any(|(tokens, _)| tokens.iter().any(|t| t == "script")) =>
+        {
+            rust_blocks
+                .into_iter()
+                .filter(|(tokens, _)| tokens.iter().any(|t| t == "script"))
+                .collect()
+        }
+        None => rust_blocks,
+    };
+
+    let mut source = String::new();
+    let mut end = 0;
+    for (start, text) in blocks.into_iter().flat_map(|(_, texts)| texts) {
+        let start = start.max(end);
+        // Whole lines in between become empty, but the start of a line the text is on becomes spaces, so the columns line up as well.
+        let gap = content.get(end..start).unwrap_or("");
+        let tail = gap.rsplit('\n').next().unwrap_or("");
+        source.extend(gap.matches('\n'));
+        source.extend(std::iter::repeat(' ').take(tail.chars().count()));
+        source.push_str(&text);
+        end = start + text.len();
+    }
+
+    Ok((manifest, source))
+}
+
+/**
+Locates the crate's doc comment, and returns its contents along with where each line came from.
+*/
+fn find_crate_doc_comment(s: &str) -> Option<(String, Origins<'_>)> {
     let re_crate_comment: Regex = {
         Regex::new(
             r"(?x)
@@ -772,57 +2607,89 @@ fn find_code_block_manifest(s: &str) -> Option<(Manifest<'_>, &str)> {
         None => return None,
     };
 
-    let comment = match extract_comment(&s[start..]) {
-        Ok(s) => s,
+    match extract_comment(&s[start..]) {
+        Ok(s) => Some(s),
         Err(err) => {
             error!("error slicing comment: {}", err);
-            return None;
+            None
         }
-    };
+    }
+}
+
+/**
+Extracts the first non-empty `Cargo` fenced code block from a chunk of Markdown.  An empty `Cargo` block is passed over, so a later one is used instead, as it always has been.
 
-    scrape_markdown_manifest(&comment).map(|m| (Manifest::TomlOwned(m), s))
+Along with the manifest, returns a list pairing offsets into the manifest with the offsets into the Markdown they were copied from.
+*/
+fn scrape_markdown_manifest(content: &str) -> Option<(String, Vec<(usize, usize)>)> {
+    scrape_markdown_block(content, |info| info.to_lowercase() == "cargo")
 }
 
+/// A fenced code block, as its info string along with its pieces of text and the offsets into the Markdown they start at.
+type CodeBlock = (String, Vec<(usize, String)>);
+
 /**
-Extracts the first `Cargo` fenced code block from a chunk of Markdown.
+Extracts every fenced code block from a chunk of Markdown.
 */
-fn scrape_markdown_manifest(content: &str) -> Option<String> {
+fn scrape_markdown_code_blocks(content: &str) -> Vec<CodeBlock> {
     use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
 
     // To match librustdoc/html/markdown.rs, opts.
     let exts = Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES;
 
-    let md = Parser::new_ext(content, exts);
+    let md = Parser::new_ext(content, exts).into_offset_iter();
 
-    let mut found = false;
-    let mut output = None;
+    let mut blocks = Vec::new();
+    let mut current = None;
 
-    for item in md {
+    for (item, range) in md {
         match item {
-            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info)))
-                if info.to_lowercase() == "cargo" && output.is_none() =>
-            {
-                found = true;
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) => {
+                current = Some((info.to_string(), Vec::new()));
             }
-            Event::Text(ref text) if found => {
-                let s = output.get_or_insert(String::new());
-                s.push_str(text);
-            }
-            Event::End(TagEnd::CodeBlock) if found => {
-                found = false;
+            Event::Text(ref text) => {
+                if let Some((_, texts)) = current.as_mut() {
+                    // Any indentation of the code block has been stripped from the front of the text, so line it up with the end of the range instead.
+                    texts.push((range.end.saturating_sub(text.len()), text.to_string()));
+                }
             }
+            Event::End(TagEnd::CodeBlock) => blocks.extend(current.take()),
             _ => (),
         }
     }
 
-    output
+    blocks
+}
+
+/**
+Extracts the first fenced code block whose info string is accepted by `is_wanted` from a chunk of Markdown, in the same way as `scrape_markdown_manifest`.  Empty code blocks are skipped.
+*/
+fn scrape_markdown_block(
+    content: &str,
+    is_wanted: impl Fn(&str) -> bool,
+) -> Option<(String, Vec<(usize, usize)>)> {
+    let (_, texts) = scrape_markdown_code_blocks(content)
+        .into_iter()
+        .find(|(info, texts)| is_wanted(info) && !texts.is_empty())?;
+
+    let mut s = String::new();
+    let mut offsets = Vec::new();
+    for (text_start, text) in texts {
+        let mut line_start = 0;
+        for line in text.split_inclusive('\n') {
+            offsets.push((s.len() + line_start, text_start + line_start));
+            line_start += line.len();
+        }
+        s.push_str(&text);
+    }
+    Some((s, offsets))
 }
 
 #[test]
 fn test_scrape_markdown_manifest() {
     macro_rules! smm {
         ($c:expr) => {
-            scrape_markdown_manifest($c)
+            scrape_markdown_manifest($c).map(|(m, _)| m)
         };
     }
 
@@ -834,6 +2701,27 @@ fn test_scrape_markdown_manifest() {
         None
     );
 
+    assert_eq!(
+        smm!(
+            r#"The first manifest is empty, so the second is used.
+
+```cargo
+```
+
+```cargo
+[dependencies]
+time = "0.1.25"
+```
+"#
+        ),
+        Some(
+            r#"[dependencies]
+time = "0.1.25"
+"#
+            .into()
+        )
+    );
+
     assert_eq!(
         smm!(
             r#"There is no manifest in this comment.
@@ -915,8 +2803,10 @@ dependencies = { explode = true }
 
 /**
 Extracts the contents of a Rust doc comment.
+
+Along with the contents, returns where each line of the contents came from in the source.
 */
-fn extract_comment(s: &str) -> MainResult<String> {
+fn extract_comment(s: &str) -> MainResult<(String, Origins<'_>)> {
     use std::cmp::min;
 
     fn n_leading_spaces(s: &str, n: usize) -> MainResult<()> {
@@ -926,7 +2816,7 @@ fn extract_comment(s: &str) -> MainResult<String> {
         Ok(())
     }
 
-    fn extract_block(s: &str) -> MainResult<String> {
+    fn extract_block(s: &str) -> MainResult<(String, Origins<'_>)> {
         /*
         On every line:
 
@@ -939,6 +2829,7 @@ fn extract_comment(s: &str) -> MainResult<String> {
         - append content
         */
         let mut r = String::new();
+        let mut origins = Vec::new();
 
         let margin_re: Regex = Regex::new(r"^\s*\*( |$)").unwrap();
         let space_re: Regex = Regex::new(r"^(\s+)").unwrap();
@@ -1002,17 +2893,19 @@ fn extract_comment(s: &str) -> MainResult<String> {
             let line = &line[strip_len..];
 
             // Done.
+            origins.push((r.len(), line));
             r.push_str(line);
 
             // `lines` removes newlines.  Ideally, it wouldn't do that, but hopefully this shouldn't cause any *real* problems.
             r.push('\n');
         }
 
-        Ok(r)
+        Ok((r, origins))
     }
 
-    fn extract_line(s: &str) -> MainResult<String> {
+    fn extract_line(s: &str) -> MainResult<(String, Origins<'_>)> {
         let mut r = String::new();
+        let mut origins = Vec::new();
 
         let comment_re = Regex::new(r"^\s*//(!|/)").unwrap();
 
@@ -1048,13 +2941,14 @@ fn extract_comment(s: &str) -> MainResult<String> {
             let content = &content[strip_len..];
 
             // Done.
+            origins.push((r.len(), content));
             r.push_str(content);
 
             // `lines` removes newlines.  Ideally, it wouldn't do that, but hopefully this shouldn't cause any *real* problems.
             r.push('\n');
         }
 
-        Ok(r)
+        Ok((r, origins))
     }
 
     if let Some(stripped) = s.strip_prefix("/*!") {
@@ -1066,11 +2960,129 @@ fn extract_comment(s: &str) -> MainResult<String> {
     }
 }
 
+#[test]
+fn test_split_markdown() {
+    let markdown = r#"# Notes
+
+```cargo
+[dependencies]
+time = "0.1.25"
+```
+
+```rust
+let a = 1;
+```
+
+- In a list:
+
+  ```rust
+  let b = 2;
+  ```
+
+```rust,ignore
+not run
+```
+
+```rust,build
+fn main() {}
+```
+
+```rust,last
+println!("{}", a + b);
+```
+"#;
+
+    let (manifest, source) = split_markdown(markdown, None).unwrap();
+    assert_eq!(
+        manifest.into_toml(markdown, "notes.md").unwrap(),
+        toml::from_str(r#"dependencies = { time = "0.1.25" }"#).unwrap()
+    );
+    assert_eq!(
+        source,
+        "\n\n\n\n\n\n\n\nlet a = 1;\n\n\n\n\n\n  let b = 2;\n\n\n\n\n\n\n\n\n\n\n\nprintln!(\"{}\", a + b);\n"
+    );
+
+    let (_, source) = split_markdown(markdown, Some("Last")).unwrap();
+    assert_eq!(source.lines().count(), 27);
+    assert_eq!(source.trim(), r#"println!("{}", a + b);"#);
+
+    // Blocks tagged `rust,script` are the only ones run, if there are any.
+    let (_, source) = split_markdown(
+        "```rust\nlet a = 1;\n```\n\n```rust,script\nlet b = 2;\n```\n",
+        None,
+    )
+    .unwrap();
+    assert_eq!(source, "\n\n\n\n\nlet b = 2;\n");
+
+    assert!(split_markdown("```rust,ignore\nnot run\n```\n", None).is_err());
+    assert!(split_markdown(markdown, Some("missing")).is_err());
+}
+
+#[test]
+fn test_find_embedded_build_script() {
+    assert_eq!(find_embedded_build_script("fn main() {}"), None);
+
+    assert_eq!(
+        find_embedded_build_script(
+            r#"//! ```cargo
+//! [build-dependencies]
+//! cc = "1"
+//! ```
+//!
+//! ```rust,build
+//! fn main() {
+//!     println!("cargo:rustc-env=GREETING=hi");
+//! }
+//! ```
+fn main() {}
+"#
+        ),
+        Some(
+            r#"fn main() {
+    println!("cargo:rustc-env=GREETING=hi");
+}
+"#
+            .into()
+        )
+    );
+
+    // Only a block marked as a build script counts, not other Rust examples.
+    assert_eq!(
+        find_embedded_build_script(
+            r#"/*!
+```rust
+fn main() {}
+```
+
+```rust, Build
+fn main() {}
+```
+*/
+fn main() {}
+"#
+        ),
+        Some("fn main() {}\n".into())
+    );
+
+    assert_eq!(
+        find_embedded_build_script(
+            r#"//! ```rust,build,ignore
+//! fn main() {}
+//! ```
+fn main() {}
+"#
+        ),
+        None
+    );
+}
+
 #[test]
 fn test_extract_comment() {
     macro_rules! ec {
         ($s:expr) => {
-            extract_comment($s).map_err(|e| e.to_string())
+            extract_comment($s)
+                .map(|(c, _)| c)
+                .map_err(|e| e.to_string())
         };
     }
 
@@ -1145,11 +3157,7 @@ time = "*"
 /**
 Generates a default Cargo manifest for the given input.
 */
-fn default_manifest(
-    bin_name: &str,
-    bin_source_path: &str,
-    toolchain: Option<String>,
-) -> toml::value::Table {
+fn default_manifest(bin_name: &str, bin_source_path: &str) -> toml::value::Table {
     let mut package_map = toml::map::Map::new();
     package_map.insert(
         "name".to_string(),
@@ -1165,22 +3173,8 @@ fn default_manifest(
     );
     package_map.insert(
         "edition".to_string(),
-        toml::value::Value::String("2021".to_string()),
-    );
-    if let Some(toolchain) = toolchain {
-        let mut metadata = toml::map::Map::new();
-        let mut rustscript_metadata = toml::map::Map::new();
-        rustscript_metadata.insert(
-            "toolchain".to_string(),
-            toml::value::Value::String(toolchain),
-        );
-        metadata.insert(
-            "rustscript".to_string(),
-            toml::value::Value::Table(rustscript_metadata),
-        );
-        package_map.insert("metadata".to_string(), toml::value::Value::Table(metadata));
-    }
-
+        toml::value::Value::String("2021".to_string()),
+    );
     let mut release_map = toml::map::Map::new();
     release_map.insert("strip".to_string(), toml::value::Value::Boolean(true));
 
@@ -1218,6 +3212,34 @@ fn default_manifest(
     mani_map
 }
 
+/**
+Generates a partial Cargo manifest recording the toolchain to build with.
+*/
+fn toolchain_manifest(toolchain: String) -> toml::value::Table {
+    let mut rustscript_metadata = toml::map::Map::new();
+    rustscript_metadata.insert(
+        "toolchain".to_string(),
+        toml::value::Value::String(toolchain),
+    );
+
+    let mut metadata = toml::map::Map::new();
+    metadata.insert(
+        "rustscript".to_string(),
+        toml::value::Value::Table(rustscript_metadata),
+    );
+
+    let mut package_map = toml::map::Map::new();
+    package_map.insert("metadata".to_string(), toml::value::Value::Table(metadata));
+
+    let mut mani_map = toml::map::Map::new();
+    mani_map.insert(
+        "package".to_string(),
+        toml::value::Value::Table(package_map),
+    );
+
+    mani_map
+}
+
 /**
 Generates a partial Cargo manifest containing the specified dependencies.
 */
@@ -1251,62 +3273,590 @@ fn deps_manifest(deps: &[(String, String)]) -> MainResult<toml::value::Table> {
 /**
 Given two Cargo manifests, merges the second *into* the first.
 
-Note that the "merge" in this case is relatively simple: only *top-level* tables are actually merged; everything else is just outright replaced.
+Tables are merged recursively.  Arrays of `[[bin]]` targets and of features are appended to; all other values in the second manifest replace those in the first.  Dependencies given in the short `name = "version"` form are expanded as needed so they can be merged with the table form.
 */
-fn merge_manifest(
+pub fn merge_manifest(
     mut into_t: toml::value::Table,
     from_t: toml::value::Table,
 ) -> MainResult<toml::value::Table> {
-    for (k, v) in from_t {
-        match v {
-            toml::Value::Table(from_t) => {
-                // Merge.
-                match into_t.entry(k) {
-                    toml::map::Entry::Vacant(e) => {
-                        e.insert(toml::Value::Table(from_t));
+    merge_table(&mut into_t, from_t, &mut Vec::new())?;
+    return Ok(into_t);
+
+    fn merge_table(
+        into_t: &mut toml::value::Table,
+        from_t: toml::value::Table,
+        path: &mut Vec<String>,
+    ) -> MainResult<()> {
+        for (k, mut from_v) in from_t {
+            path.push(k.clone());
+            match into_t.entry(k) {
+                toml::map::Entry::Vacant(e) => {
+                    e.insert(from_v);
+                }
+                toml::map::Entry::Occupied(e) => {
+                    let into_v = e.into_mut();
+                    if is_dependency(path) && (into_v.is_table() || from_v.is_table()) {
+                        expand_dependency(into_v);
+                        expand_dependency(&mut from_v);
                     }
-                    toml::map::Entry::Occupied(e) => {
-                        let into_t = as_table_mut(e.into_mut()).ok_or(
-                            "cannot merge manifests: cannot merge \
-                                table and non-table values",
-                        )?;
-                        into_t.extend(from_t);
+                    match (into_v, from_v) {
+                        (toml::Value::Table(into_t), toml::Value::Table(from_t)) => {
+                            merge_table(into_t, from_t, path)?;
+                        }
+                        // A target with the same name as one already there is the same target, so its settings are merged in, rather than duplicating it.
+                        (toml::Value::Array(into_a), toml::Value::Array(from_a))
+                            if path.len() == 1 && path[0] == "bin" =>
+                        {
+                            for from_bin in from_a {
+                                let name = from_bin.get("name").cloned();
+                                let same = into_a
+                                    .iter_mut()
+                                    .find(|b| name.is_some() && b.get("name") == name.as_ref());
+                                match (same, from_bin) {
+                                    (
+                                        Some(toml::Value::Table(into_bin)),
+                                        toml::Value::Table(from_bin),
+                                    ) => merge_table(into_bin, from_bin, path)?,
+                                    (_, from_bin) => into_a.push(from_bin),
+                                }
+                            }
+                        }
+                        (toml::Value::Array(into_a), toml::Value::Array(from_a))
+                            if is_appendable(path) =>
+                        {
+                            for v in from_a {
+                                if !into_a.contains(&v) {
+                                    into_a.push(v);
+                                }
+                            }
+                        }
+                        (toml::Value::Table(_), _) | (_, toml::Value::Table(_)) => {
+                            return Err(format!(
+                                "cannot merge manifests: `{}` is a table in one manifest, \
+                                    but not in the other",
+                                path.join(".")
+                            )
+                            .into());
+                        }
+                        (into_v, from_v) => {
+                            *into_v = from_v;
+                        }
+                    }
+                }
+            }
+            path.pop();
+        }
+        Ok(())
+    }
+
+    fn is_appendable(path: &[String]) -> bool {
+        match path {
+            [features, _] if features == "features" => true,
+            [.., last] => last == "features",
+            [] => false,
+        }
+    }
+}
+
+#[test]
+fn test_merge_manifest() {
+    macro_rules! mm {
+        ($into:expr, $from:expr) => {
+            merge_manifest(
+                toml::from_str($into).unwrap(),
+                toml::from_str($from).unwrap(),
+            )
+            .map(|m| m.to_string())
+            .map_err(|e| e.to_string())
+        };
+    }
+
+    assert_eq!(
+        mm!(
+            r#"
+[package.metadata.rustscript]
+toolchain = "nightly"
+"#,
+            r#"
+[package.metadata.x]
+y = 1
+"#
+        ),
+        Ok(r#"[package.metadata.rustscript]
+toolchain = "nightly"
+
+[package.metadata.x]
+y = 1
+"#
+        .into())
+    );
+
+    assert_eq!(
+        mm!(
+            r#"
+[dependencies]
+serde = "*"
+"#,
+            r#"
+[dependencies.serde]
+features = ["derive"]
+"#
+        ),
+        Ok(r#"[dependencies.serde]
+features = ["derive"]
+version = "*"
+"#
+        .into())
+    );
+
+    assert_eq!(
+        mm!(
+            r#"
+[dependencies]
+serde = { version = "1", features = ["derive"] }
+"#,
+            r#"
+[dependencies]
+serde = { version = "1.0.100", features = ["rc", "derive"] }
+"#
+        ),
+        Ok(r#"[dependencies.serde]
+features = ["derive", "rc"]
+version = "1.0.100"
+"#
+        .into())
+    );
+
+    assert_eq!(
+        mm!(
+            r#"
+[[bin]]
+name = "a"
+"#,
+            r#"
+[[bin]]
+name = "b"
+"#
+        ),
+        Ok(r#"[[bin]]
+name = "a"
+
+[[bin]]
+name = "b"
+"#
+        .into())
+    );
+
+    // The script's values win for a bin with the same name, such as the default one.
+    assert_eq!(
+        mm!(
+            r#"
+[[bin]]
+name = "a"
+path = "a.rs"
+"#,
+            r#"
+[[bin]]
+name = "a"
+path = "other.rs"
+required-features = ["x"]
+"#
+        ),
+        Ok(r#"[[bin]]
+name = "a"
+path = "other.rs"
+required-features = ["x"]
+"#
+        .into())
+    );
+
+    assert_eq!(
+        mm!(
+            r#"
+[package]
+edition = "2018"
+"#,
+            r#"
+[package]
+edition = "2021"
+"#
+        ),
+        Ok(r#"[package]
+edition = "2021"
+"#
+        .into())
+    );
+
+    assert_eq!(
+        mm!(
+            r#"
+[package]
+name = "a"
+"#,
+            r#"
+package = "b"
+"#
+        ),
+        Err(
+            "cannot merge manifests: `package` is a table in one manifest, but not in the other"
+                .into()
+        )
+    );
+}
+
+#[test]
+fn test_check_dep_conflicts() {
+    macro_rules! cdc {
+        ($script:expr, $cli:expr) => {
+            check_dep_conflicts(
+                &toml::from_str($script).unwrap(),
+                &toml::from_str($cli).unwrap(),
+            )
+            .map_err(|e| e.to_string())
+        };
+    }
+
+    assert_eq!(
+        cdc!(
+            r#"
+[dependencies]
+serde = "1"
+"#,
+            r#"
+[dependencies]
+serde = "*"
+"#
+        ),
+        Ok(())
+    );
+
+    assert_eq!(
+        cdc!(
+            r#"
+[dependencies]
+serde = { version = "1", features = ["derive"] }
+"#,
+            r#"
+[dependencies]
+serde = { version = "1", features = ["rc"] }
+"#
+        ),
+        Ok(())
+    );
+
+    assert_eq!(
+        cdc!(
+            r#"
+[dependencies]
+serde = "1.0.200"
+"#,
+            r#"
+[dependencies]
+serde = "1.0.100"
+"#
+        ),
+        Err("dependency `serde` is given with version = \"1.0.100\" on the command line, but with version = \"1.0.200\" in the script".into())
+    );
+
+    assert_eq!(
+        cdc!(
+            r#"
+[dev-dependencies]
+serde = "1.0.200"
+"#,
+            r#"
+[dependencies]
+serde = "1.0.100"
+"#
+        ),
+        Err("dependency `serde` is given with version = \"1.0.100\" on the command line, but with version = \"1.0.200\" in the script's `[dev-dependencies]`".into())
+    );
+
+    assert_eq!(
+        cdc!(
+            r#"
+[target.'cfg(unix)'.dependencies]
+serde = { version = "1", default-features = false }
+"#,
+            r#"
+[dependencies]
+serde = { version = "1", default-features = true }
+"#
+        ),
+        Err("dependency `serde` is given with default-features = true on the command line, but with default-features = false in the script's `[target.cfg(unix).dependencies]`".into())
+    );
+}
+
+/// Is the given manifest path that of a single dependency (*e.g.* `dependencies.serde`)?
+fn is_dependency(path: &[String]) -> bool {
+    match path {
+        [.., table, _] => {
+            table == "dependencies" || table == "dev-dependencies" || table == "build-dependencies"
+        }
+        _ => false,
+    }
+}
+
+/// Expands a dependency given as just a version requirement into its table form.
+fn expand_dependency(dep: &mut toml::Value) {
+    if let toml::Value::String(version) = dep {
+        let mut table = toml::value::Table::new();
+        table.insert(
+            "version".to_string(),
+            toml::Value::String(std::mem::take(version)),
+        );
+        *dep = toml::Value::Table(table);
+    }
+}
+
+/**
+Checks that dependencies given on the command line don't contradict those embedded in the script, in any of its dependency tables.
+
+A dependency given without a version (*i.e.* `*`) doesn't conflict with any version in the script, and features are combined rather than compared.
+*/
+fn check_dep_conflicts(
+    script_mani: &toml::value::Table,
+    cli_mani: &toml::value::Table,
+) -> MainResult<()> {
+    let script_deps = dependency_entries(script_mani);
+    for (_, name, cli_dep) in dependency_entries(cli_mani) {
+        for (key, _, script_dep) in script_deps
+            .iter()
+            .filter(|(_, script_name, _)| *script_name == name)
+        {
+            let (mut cli_dep, mut script_dep) = (cli_dep.clone(), (*script_dep).clone());
+            expand_dependency(&mut cli_dep);
+            expand_dependency(&mut script_dep);
+            let (toml::Value::Table(cli_dep), toml::Value::Table(script_dep)) =
+                (cli_dep, script_dep)
+            else {
+                continue;
+            };
+
+            // The key is the table, then the name.
+            let table = &key[..key.len() - name.len() - 1];
+            let place = if table == "dependencies" {
+                "the script".to_string()
+            } else {
+                format!("the script's `[{}]`", table)
+            };
+            for (field, cli_v) in &cli_dep {
+                match script_dep.get(field) {
+                    _ if field == "features" => {}
+                    _ if field == "version" && cli_v.as_str() == Some("*") => {}
+                    Some(script_v) if script_v != cli_v => {
+                        return Err(format!(
+                            "dependency `{}` is given with {} = {} on the command line, \
+                                but with {} = {} in {}",
+                            name, field, cli_v, field, script_v, place
+                        )
+                        .into());
                     }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/**
+Formats a manifest for people to read and edit, with `[package]` first.
+*/
+pub fn format_manifest(mut mani: toml::value::Table) -> String {
+    let mut mani_str = String::new();
+    if let Some(package) = mani.remove("package") {
+        let mut package_mani = toml::value::Table::new();
+        package_mani.insert("package".into(), package);
+        mani_str.push_str(&format!("{}\n", package_mani));
+    }
+    mani_str.push_str(&mani.to_string());
+    mani_str.truncate(mani_str.trim_end().len());
+    mani_str.push('\n');
+    mani_str
+}
+
+/// Values in a manifest which are file paths, and need to be rewritten when the manifest is relocated.
+const MANIFEST_PATHS: &[&[&str]] = &[
+    &["build-dependencies", "*", "path"],
+    &["build-dependencies", "*", "script"],
+    &["dependencies", "*", "path"],
+    &["dependencies", "*", "script"],
+    &["dev-dependencies", "*", "path"],
+    &["dev-dependencies", "*", "script"],
+    &["package", "build"],
+    &["target", "*", "dependencies", "*", "path"],
+    &["target", "*", "dependencies", "*", "script"],
+];
+
+/**
+Returns every dependency in the manifest, from all dependency tables including platform-specific ones.
+
+Each comes with a key identifying it across manifests, and its name.
+*/
+fn dependency_entries(mani: &toml::value::Table) -> Vec<(String, &str, &toml::Value)> {
+    const TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+    fn push_tables<'m>(
+        entries: &mut Vec<(String, &'m str, &'m toml::Value)>,
+        prefix: &str,
+        table: &'m toml::value::Table,
+    ) {
+        for table_name in TABLES {
+            if let Some(toml::Value::Table(deps)) = table.get(*table_name) {
+                for (name, value) in deps {
+                    let key = format!("{}{}.{}", prefix, table_name, name);
+                    entries.push((key, name.as_str(), value));
                 }
             }
-            v => {
-                // Just replace.
-                into_t.insert(k, v);
+        }
+    }
+
+    let mut entries = Vec::new();
+    push_tables(&mut entries, "", mani);
+    if let Some(toml::Value::Table(targets)) = mani.get("target") {
+        for (target, table) in targets {
+            if let toml::Value::Table(table) = table {
+                push_tables(&mut entries, &format!("target.{}.", target), table);
             }
         }
     }
+    entries
+}
 
-    return Ok(into_t);
+/**
+Checks the dependencies of a manifest against the policy for dependencies that aren't pinned: those which allow any version, and git dependencies without a `rev`.
 
-    fn as_table_mut(t: &mut toml::Value) -> Option<&mut toml::value::Table> {
-        match t {
-            toml::Value::Table(t) => Some(t),
-            _ => None,
+`source_of` tells where a dependency came from, given its key from `dependency_entries`.
+*/
+pub fn check_unpinned_deps<'a>(
+    mani: &toml::value::Table,
+    source_of: impl Fn(&str) -> &'a str,
+    policy: WildcardPolicy,
+) -> MainResult<()> {
+    if policy == WildcardPolicy::Allow {
+        return Ok(());
+    }
+
+    let mut problems = Vec::new();
+    for (key, name, value) in dependency_entries(mani) {
+        let is_wildcard = |version: Option<&toml::Value>| {
+            version
+                .and_then(|v| v.as_str())
+                .map_or(true, |v| v.trim() == "*")
+        };
+        let problem = match value {
+            toml::Value::String(_) if is_wildcard(Some(value)) => "allows any version",
+            toml::Value::Table(dep) if dep.contains_key("git") => {
+                if dep.contains_key("rev") {
+                    continue;
+                }
+                "is a git dependency without a pinned `rev`"
+            }
+            toml::Value::Table(dep)
+                if !dep.contains_key("path")
+                    && !dep.contains_key("workspace")
+                    && is_wildcard(dep.get("version")) =>
+            {
+                "allows any version"
+            }
+            _ => continue,
+        };
+        problems.push(format!(
+            "dependency `{}` {} (from {})",
+            name,
+            problem,
+            source_of(&key)
+        ));
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    if policy == WildcardPolicy::Warn {
+        for problem in &problems {
+            eprintln!("warning: {}", problem);
         }
+        return Ok(());
     }
+    Err(format!(
+        "dependencies must be pinned to a version or `rev`:\n  {}",
+        problems.join("\n  ")
+    )
+    .into())
+}
+
+#[test]
+fn test_check_unpinned_deps() {
+    macro_rules! cud {
+        ($mani:expr) => {
+            check_unpinned_deps(
+                &toml::from_str($mani).unwrap(),
+                |key| {
+                    if key.starts_with("target") {
+                        "the short comment"
+                    } else {
+                        "the command line"
+                    }
+                },
+                WildcardPolicy::Deny,
+            )
+            .map_err(|e| e.to_string())
+        };
+    }
+
+    assert_eq!(
+        cud!(
+            r#"
+[dependencies]
+serde = "1"
+time = { version = "0.3", features = ["macros"] }
+mylib = { path = "/mylib" }
+otherlib = { git = "https://example.com/otherlib.git", rev = "abc123" }
+"#
+        ),
+        Ok(())
+    );
+
+    assert_eq!(
+        cud!(
+            r#"
+[dependencies]
+serde = "*"
+time = { features = ["macros"] }
+otherlib = { git = "https://example.com/otherlib.git", branch = "main" }
+
+[target.'cfg(unix)'.dependencies]
+libc = " * "
+"#
+        ),
+        Err("dependencies must be pinned to a version or `rev`:
+  dependency `otherlib` is a git dependency without a pinned `rev` (from the command line)
+  dependency `serde` allows any version (from the command line)
+  dependency `time` allows any version (from the command line)
+  dependency `libc` allows any version (from the short comment)"
+            .into())
+    );
+
+    assert_eq!(
+        check_unpinned_deps(
+            &toml::from_str("[dependencies]\nserde = \"*\"").unwrap(),
+            |_| "",
+            WildcardPolicy::Warn
+        )
+        .ok(),
+        Some(())
+    );
 }
 
 /**
 Given a Cargo manifest, attempts to rewrite relative file paths to absolute ones, allowing the manifest to be relocated.
 */
-fn fix_manifest_paths(mani: toml::value::Table, base: &Path) -> MainResult<toml::value::Table> {
-    // Values that need to be rewritten:
-    let paths: &[&[&str]] = &[
-        &["build-dependencies", "*", "path"],
-        &["dependencies", "*", "path"],
-        &["dev-dependencies", "*", "path"],
-        &["package", "build"],
-        &["target", "*", "dependencies", "*", "path"],
-    ];
-
+pub fn fix_manifest_paths(mani: toml::value::Table, base: &Path) -> MainResult<toml::value::Table> {
     let mut mani = toml::Value::Table(mani);
 
-    for path in paths {
+    for path in MANIFEST_PATHS {
         iterate_toml_mut_path(&mut mani, path, &mut |v| {
             if let toml::Value::String(s) = v {
                 if Path::new(s).is_relative() {
@@ -1326,6 +3876,89 @@ fn fix_manifest_paths(mani: toml::value::Table, base: &Path) -> MainResult<toml:
     }
 }
 
+/**
+The reverse of `fix_manifest_paths`: rewrites absolute file paths to ones relative to `base`, for a manifest that is moved to `base`.
+*/
+pub fn unfix_manifest_paths(
+    mani: toml::value::Table,
+    base: &Path,
+) -> MainResult<toml::value::Table> {
+    let mut mani = toml::Value::Table(mani);
+
+    for path in MANIFEST_PATHS {
+        iterate_toml_mut_path(&mut mani, path, &mut |v| {
+            if let toml::Value::String(s) = v {
+                if Path::new(s).is_absolute() {
+                    if let Some(p) = relative_path(Path::new(s), base).to_str() {
+                        *s = p.replace('\\', "/");
+                    }
+                }
+            }
+            Ok(())
+        })?
+    }
+
+    match mani {
+        toml::Value::Table(mani) => Ok(mani),
+        _ => unreachable!(),
+    }
+}
+
+/**
+Returns `path` relative to the directory `base`, working only on the paths themselves.
+
+Both paths must be absolute.  If they don't share a root (different drives on Windows), `path` is returned as-is.
+*/
+pub fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    use std::path::Component;
+
+    fn normalize(path: &Path) -> Vec<Component<'_>> {
+        let mut components = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir if matches!(components.last(), Some(Component::Normal(_))) => {
+                    components.pop();
+                }
+                component => components.push(component),
+            }
+        }
+        components
+    }
+
+    let path_components = normalize(path);
+    let base_components = normalize(base);
+    if path_components.first() != base_components.first() {
+        return path.into();
+    }
+
+    let common = path_components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative: PathBuf = base_components[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .collect();
+    relative.extend(&path_components[common..]);
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+#[cfg(unix)]
+#[test]
+fn test_relative_path() {
+    let rp = |p: &str, b: &str| relative_path(Path::new(p), Path::new(b));
+
+    assert_eq!(rp("/a/b/c", "/a/b"), PathBuf::from("c"));
+    assert_eq!(rp("/a/lib", "/a/b/c"), PathBuf::from("../../lib"));
+    assert_eq!(rp("/a/b/../lib", "/a/./c"), PathBuf::from("../lib"));
+    assert_eq!(rp("/a", "/a"), PathBuf::from("."));
+}
+
 /**
 Iterates over the specified TOML values via a path specification.
 */
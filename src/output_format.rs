@@ -0,0 +1,99 @@
+/*!
+This module is concerned with how the value of an `--expr` or `--loop` script is printed.
+*/
+use sha1::{Digest, Sha1};
+
+/**
+How the value of an expression, or of each call of a loop closure, is printed.  Values of `()` are never printed.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// With `{:?}`, one value per line.
+    #[default]
+    Debug,
+    /// With `{:#?}`.
+    Pretty,
+    /// With `{}`, so strings come out as they are.
+    Display,
+    /// As JSON, on one line, with `serde_json`.
+    Json,
+    /// With `{}`, and without a line ending, so that a loop can pass on lines which still have theirs.
+    Raw,
+}
+
+impl OutputFormat {
+    pub const NAMES: &'static [&'static str] = &["debug", "pretty", "display", "json", "raw"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "debug" => Some(Self::Debug),
+            "pretty" => Some(Self::Pretty),
+            "display" => Some(Self::Display),
+            "json" => Some(Self::Json),
+            "raw" => Some(Self::Raw),
+            _ => None,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match *self {
+            Self::Debug => "debug",
+            Self::Pretty => "pretty",
+            Self::Display => "display",
+            Self::Json => "json",
+            Self::Raw => "raw",
+        }
+    }
+
+    /**
+    Adds the format to the hash the package ID is made from.  The default adds nothing, so that existing packages keep their IDs.
+    */
+    pub fn hash(&self, hasher: &mut Sha1) {
+        if *self != Self::Debug {
+            hasher.update("format:");
+            hasher.update(self.name());
+            hasher.update(";");
+        }
+    }
+
+    /**
+    Returns the substitutions for `EXPR_TEMPLATE` and `LOOP_TEMPLATE`: `print_output`, the items which print a value with `(&Output(&value)).print_output()`, or `print_value(&value)` where its type is generic, and `output_bound`, the trait the value needs for that.
+
+    `()` has no `Display` or `Serialize`, so rather than a bound on a generic function, it gets an impl of its own which method resolution finds before the one which prints.  This only works where the type of the value is known, which it always is in the templates.
+    */
+    pub fn template_subs(&self) -> Vec<(&'static str, String)> {
+        let (bound, print) = match self {
+            Self::Debug => ("std::fmt::Debug", "println!(\"{:?}\", value);"),
+            Self::Pretty => ("std::fmt::Debug", "println!(\"{:#?}\", value);"),
+            Self::Display => ("std::fmt::Display", "println!(\"{}\", value);"),
+            Self::Json => (
+                "serde::Serialize",
+                "match serde_json::to_string(value) {\n        Ok(json) => println!(\"{}\", json),\n        Err(e) => {\n            eprintln!(\"error: could not write output as JSON: {}\", e);\n            std::process::exit(1);\n        }\n    }",
+            ),
+            Self::Raw => ("std::fmt::Display", "print!(\"{}\", value);"),
+        };
+        vec![
+            ("output_bound", bound.into()),
+            (
+                "print_output",
+                format!(
+                    "#[allow(dead_code)]\nfn print_value<T: {0} + ?Sized>(value: &T) {{\n    {1}\n}}\n\n#[allow(dead_code)]\nstruct Output<'a, T>(&'a T);\n\n#[allow(dead_code)]\ntrait SkipUnit {{\n    fn print_output(&self);\n}}\n\nimpl SkipUnit for Output<'_, ()> {{\n    fn print_output(&self) {{}}\n}}\n\n#[allow(dead_code)]\ntrait PrintOutput {{\n    fn print_output(&self);\n}}\n\nimpl<T: {0}> PrintOutput for &Output<'_, T> {{\n    fn print_output(&self) {{\n        print_value(self.0);\n    }}\n}}",
+                    bound, print
+                ),
+            ),
+        ]
+    }
+
+    /**
+    Returns the dependencies the generated program needs, in the same form as those given with `--dep`.
+    */
+    pub fn dependencies(&self) -> Vec<(String, String)> {
+        match self {
+            Self::Json => vec![
+                ("serde".into(), "1".into()),
+                ("serde_json".into(), "1".into()),
+            ],
+            _ => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+/*!
+This module is concerned with turning a Cargo project into a single-file script, the reverse of ejecting.
+*/
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::error::{MainError, MainResult};
+use crate::manifest;
+
+/**
+Packs the binary crate in `project_dir` into a script, and returns the path it was written to.
+
+The script is written to `script_path` if given, or to a file named after the package in the current directory.  Existing files are never overwritten.
+*/
+pub fn pack(project_dir: &Path, script_path: Option<&Path>) -> MainResult<PathBuf> {
+    let cwd = std::env::current_dir()?;
+    let project_dir = cwd.join(project_dir);
+    let src_dir = project_dir.join("src");
+
+    let mani_path = project_dir.join("Cargo.toml");
+    let mani_str = fs::read_to_string(&mani_path).map_err(|e| {
+        MainError::Tag(
+            format!("could not read {}", mani_path.display()).into(),
+            Box::new(e.into()),
+        )
+    })?;
+    let mut mani: toml::value::Table = toml::from_str(&mani_str).map_err(|e| {
+        MainError::Tag(
+            format!("could not parse {}", mani_path.display()).into(),
+            Box::new(MainError::Other(Box::new(e))),
+        )
+    })?;
+
+    let package = match mani.get_mut("package") {
+        Some(toml::Value::Table(package)) => package,
+        _ => return Err(format!("{} has no `[package]`", mani_path.display()).into()),
+    };
+    let name = package
+        .remove("name")
+        .and_then(|name| name.as_str().map(String::from))
+        .ok_or_else(|| format!("{} has no package name", mani_path.display()))?;
+
+    let build_script_path = match package.remove("build") {
+        Some(toml::Value::String(path)) => Some(project_dir.join(path)),
+        Some(toml::Value::Boolean(false)) => None,
+        _ => Some(project_dir.join("build.rs")).filter(|path| path.exists()),
+    };
+
+    // A script is a single binary, so anything that describes other targets has no place in it.
+    if src_dir.join("lib.rs").exists() || mani.contains_key("lib") {
+        return Err("cannot pack a project with a library target".into());
+    }
+    if mani.contains_key("workspace") {
+        return Err("cannot pack a workspace".into());
+    }
+    // The one binary may have been moved from `src/main.rs`.
+    let main_path = match mani.remove("bin") {
+        None => src_dir.join("main.rs"),
+        Some(toml::Value::Array(bins)) if bins.len() == 1 => match bins[0].get("path") {
+            None => src_dir.join("main.rs"),
+            Some(toml::Value::String(path)) => project_dir.join(path),
+            Some(_) => {
+                return Err(format!("{} has an invalid `bin.path`", mani_path.display()).into())
+            }
+        },
+        Some(_) => return Err("cannot pack a project with more than one binary target".into()),
+    };
+
+    let script_path = match script_path {
+        Some(path) => cwd.join(path),
+        None => cwd.join(format!("{}.rs", name)),
+    };
+    if script_path.exists() {
+        return Err(format!(
+            "cannot pack into {}: the file already exists",
+            script_path.display()
+        )
+        .into());
+    }
+    let script_dir = script_path
+        .parent()
+        .expect("couldn't get parent directory for script path");
+
+    // Paths in the manifest are relative to the project, but in a script they are relative to the script.
+    let mani = manifest::fix_manifest_paths(mani, &project_dir)?;
+    let mani = manifest::unfix_manifest_paths(mani, script_dir)?;
+
+    let source = fs::read_to_string(&main_path).map_err(|e| {
+        MainError::Tag(
+            format!("could not read {}", main_path.display()).into(),
+            Box::new(e.into()),
+        )
+    })?;
+    // Like any crate root, its modules are next to it.
+    let main_dir = main_path
+        .parent()
+        .expect("couldn't get parent directory for the binary");
+    let source = inline_modules(&source, main_dir)?;
+    let build_script = build_script_path
+        .map(|path| {
+            fs::read_to_string(&path).map_err(|e| {
+                MainError::Tag(
+                    format!("could not read {}", path.display()).into(),
+                    Box::new(e.into()),
+                )
+            })
+        })
+        .transpose()?;
+
+    let mut script = String::from("#!/usr/bin/env rust-script\n");
+    script.push_str("//! ```cargo\n");
+    for line in manifest::format_manifest(mani).lines() {
+        script.push_str(format!("//! {}", line).trim_end());
+        script.push('\n');
+    }
+    script.push_str("//! ```\n");
+    if let Some(build_script) = build_script {
+        script.push_str("//!\n//! ```rust,build\n");
+        for line in build_script.lines() {
+            script.push_str(format!("//! {}", line).trim_end());
+            script.push('\n');
+        }
+        script.push_str("//! ```\n");
+    }
+    // Merge a crate doc comment into the one holding the manifest, since only the first one is looked at.
+    if source.starts_with("//!") {
+        script.push_str("//!\n");
+    }
+    script.push_str(&source);
+
+    info!("packing into {:?}", script_path);
+    fs::write(&script_path, script)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = fs::metadata(&script_path)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o111);
+        fs::set_permissions(&script_path, permissions)?;
+    }
+
+    Ok(script_path)
+}
+
+/**
+Replaces each `mod name;` declaration in `source` with a `mod name { ... }` block holding the module's file, recursively.
+
+`dir` is where the files of submodules of `source` are looked for, following the same rules as rustc.
+*/
+fn inline_modules(source: &str, dir: &Path) -> MainResult<String> {
+    if source.contains("#[path") {
+        return Err("cannot pack modules with a `#[path]` attribute".into());
+    }
+
+    let mut result = String::with_capacity(source.len());
+    let mut anchor = 0;
+    for decl in manifest::find_mod_declarations(source) {
+        // Modules declared inside inline modules are looked for in directories named after them.
+        let dir = decl
+            .parents
+            .iter()
+            .fold(dir.to_path_buf(), |d, p| d.join(p));
+        let name = decl.name.trim_start_matches("r#");
+
+        // Either way, the module's own submodules live in a directory named after it.
+        let sub_dir = dir.join(name);
+        let path = [dir.join(format!("{}.rs", name)), sub_dir.join("mod.rs")]
+            .into_iter()
+            .find(|path| path.exists())
+            .ok_or_else(|| {
+                format!(
+                    "could not find the file for module `{}` in {}",
+                    name,
+                    dir.display()
+                )
+            })?;
+        info!("inlining module {:?}", path);
+
+        let module = fs::read_to_string(&path)?;
+        let module = inline_modules(&module, &sub_dir)?;
+        result.push_str(&source[anchor..decl.range.start]);
+        result.push_str(&format!("{}mod {} {{\n", decl.visibility, decl.name));
+        result.push_str(&module);
+        if !module.ends_with('\n') {
+            result.push('\n');
+        }
+        result.push('}');
+        anchor = decl.range.end;
+    }
+    result.push_str(&source[anchor..]);
+    Ok(result)
+}
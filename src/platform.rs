@@ -2,7 +2,7 @@
 This module is for platform-specific stuff.
 */
 
-pub use self::inner::force_cargo_color;
+pub use self::inner::{force_cargo_color, unbuffered_stdin};
 
 use std::fs;
 
@@ -44,6 +44,11 @@ pub fn cache_dir() -> PathBuf {
     }
 }
 
+/// The path of the user's configuration file, which may not exist.
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(crate::consts::PROGRAM_NAME).join("config.toml"))
+}
+
 pub fn generated_projects_cache_path() -> PathBuf {
     cache_dir().join("projects")
 }
@@ -52,6 +57,16 @@ pub fn binary_cache_path() -> PathBuf {
     cache_dir().join("binaries")
 }
 
+/// Where scripts fetched from URLs are kept, by their SHA-256 digest.
+pub fn fetched_scripts_cache_path() -> PathBuf {
+    cache_dir().join("scripts")
+}
+
+/// Where the history of `--repl` sessions is kept.
+pub fn repl_history_path() -> PathBuf {
+    cache_dir().join("repl-history.txt")
+}
+
 #[cfg(unix)]
 mod inner {
     use std::io::IsTerminal as _;
@@ -64,6 +79,14 @@ mod inner {
     pub fn force_cargo_color() -> bool {
         std::io::stderr().is_terminal()
     }
+
+    /**
+    Returns a handle to stdin which reads directly from it, so that nothing is read ahead that a child process would miss.
+    */
+    pub fn unbuffered_stdin() -> std::io::Result<std::fs::File> {
+        use std::os::fd::AsFd;
+        Ok(std::io::stdin().as_fd().try_clone_to_owned()?.into())
+    }
 }
 
 #[cfg(windows)]
@@ -76,4 +99,12 @@ pub mod inner {
     pub fn force_cargo_color() -> bool {
         false
     }
+
+    /**
+    Returns a handle to stdin which reads directly from it, so that nothing is read ahead that a child process would miss.
+    */
+    pub fn unbuffered_stdin() -> std::io::Result<std::fs::File> {
+        use std::os::windows::io::AsHandle;
+        Ok(std::io::stdin().as_handle().try_clone_to_owned()?.into())
+    }
 }
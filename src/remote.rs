@@ -0,0 +1,202 @@
+/*!
+This module is concerned with scripts given as `file://`, `http://` or `https://` URLs.
+
+A script from a URL is only used once its SHA-256 digest matches the one it is pinned to.  The verified copy is kept in the cache under its digest, and compiled from there, so a pinned script is fetched only once and can't change after it has been checked.
+*/
+use std::fs;
+use std::path::PathBuf;
+
+use log::info;
+use sha2::{Digest, Sha256};
+
+use crate::error::{MainError, MainResult};
+use crate::platform;
+
+/// The largest script that will be downloaded.
+#[cfg(feature = "remote")]
+const MAX_SCRIPT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Returns `true` if the script is given as a URL rather than a path.
+pub fn is_url(script: &str) -> bool {
+    ["file://", "http://", "https://"].iter().any(|scheme| {
+        script
+            .get(..scheme.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(scheme))
+    })
+}
+
+/**
+Fetches the script at `url`, checks it against the `sha256` digest it is pinned to, and returns the path of the verified copy in the cache.
+*/
+pub fn fetch_script(url: &str, sha256: Option<&str>) -> MainResult<PathBuf> {
+    let expected = sha256
+        .ok_or_else(|| {
+            format!(
+                "the script at {} must be pinned with `--sha256`, or in the `[sha256]` table of the config file",
+                url
+            )
+        })?
+        .to_ascii_lowercase();
+    if expected.len() != 64 || !expected.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("`{}` is not a SHA-256 digest", expected).into());
+    }
+
+    let dir = platform::fetched_scripts_cache_path().join(&expected);
+    let path = dir.join(file_name(url));
+    if fs::read(&path).is_ok_and(|content| digest(&content) == expected) {
+        info!("using cached copy {:?} of {}", path, url);
+        return Ok(path);
+    }
+
+    info!("fetching {}", url);
+    let content = if url[..7].eq_ignore_ascii_case("file://") {
+        let local_path = path_from_file_url(url)?;
+        fs::read(&local_path).map_err(|e| {
+            MainError::Tag(
+                format!("could not read {}", local_path.display()).into(),
+                Box::new(e.into()),
+            )
+        })?
+    } else {
+        http_get(url)?
+    };
+
+    let actual = digest(&content);
+    if actual != expected {
+        return Err(format!(
+            "the script at {} has SHA-256 digest {}, but {} was expected",
+            url, actual, expected
+        )
+        .into());
+    }
+    let content = String::from_utf8(content)
+        .map_err(|_| format!("the script at {} is not valid UTF-8", url))?;
+
+    fs::create_dir_all(&dir)?;
+    crate::overwrite_file(&path, &content)?;
+    Ok(path)
+}
+
+/// Returns the SHA-256 digest of `content`, in lowercase hex.
+fn digest(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+#[cfg(feature = "remote")]
+fn http_get(url: &str) -> MainResult<Vec<u8>> {
+    use std::io::Read;
+
+    let response = ureq::get(url).call().map_err(|e| {
+        MainError::Tag(
+            format!("could not fetch {}", url).into(),
+            Box::new(MainError::Other(Box::new(e))),
+        )
+    })?;
+    let mut content = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_SCRIPT_SIZE + 1)
+        .read_to_end(&mut content)?;
+    if content.len() as u64 > MAX_SCRIPT_SIZE {
+        return Err(format!("the script at {} is too large", url).into());
+    }
+    Ok(content)
+}
+
+#[cfg(not(feature = "remote"))]
+fn http_get(url: &str) -> MainResult<Vec<u8>> {
+    Err(format!(
+        "cannot fetch {}: rust-script was built without the `remote` feature, which `cargo install rust-script --features remote` enables",
+        url
+    )
+    .into())
+}
+
+/**
+Returns the name for the cached copy of the script at `url`: the last segment of its path, made safe to use as a file name.
+*/
+fn file_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let name = percent_decode(path.rsplit('/').next().unwrap_or_default());
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    if name.trim_matches('.').is_empty() {
+        "script.rs".into()
+    } else {
+        name
+    }
+}
+
+/**
+Turns a `file://` URL into a path.  Only URLs without a host, or for `localhost`, are supported.
+*/
+fn path_from_file_url(url: &str) -> MainResult<PathBuf> {
+    let rest = &url["file://".len()..];
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if !(host.is_empty() || host.eq_ignore_ascii_case("localhost")) {
+        return Err(format!("cannot read {}: only local file URLs are supported", url).into());
+    }
+    let path = percent_decode(path.split(['?', '#']).next().unwrap_or_default());
+    // `file:///C:/script.rs` on Windows.
+    let path = if cfg!(windows) {
+        path.strip_prefix('/').unwrap_or(&path).to_string()
+    } else {
+        path
+    };
+    Ok(path.into())
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[test]
+fn test_urls() {
+    assert!(is_url("https://example.com/tool.rs"));
+    assert!(is_url("FILE:///tmp/tool.rs"));
+    assert!(!is_url("tool.rs"));
+    assert!(!is_url("http"));
+
+    assert_eq!(
+        file_name("https://example.com/ops/clean%20up.rs?v=2"),
+        "clean_up.rs"
+    );
+    assert_eq!(file_name("https://example.com/"), "script.rs");
+
+    #[cfg(unix)]
+    {
+        assert_eq!(
+            path_from_file_url("file:///tmp/my%20script.rs").ok(),
+            Some(PathBuf::from("/tmp/my script.rs"))
+        );
+        assert_eq!(
+            path_from_file_url("file://localhost/tmp/a.rs").ok(),
+            Some(PathBuf::from("/tmp/a.rs"))
+        );
+    }
+    assert!(path_from_file_url("file://server/share/a.rs").is_err());
+}
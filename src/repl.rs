@@ -0,0 +1,376 @@
+/*!
+This module is concerned with the interactive `--repl` mode.
+
+Each input is run by building a program which replays the session so far and then runs the input, in the same way as an expression given with `--expr`.  All of these programs share one package, so that only the changes have to be rebuilt, which is removed when the session ends.
+*/
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+
+use log::info;
+use regex::Regex;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::arguments::Args;
+use crate::dependency::{DepSpec, WildcardPolicy};
+use crate::error::MainResult;
+use crate::platform;
+use crate::Input;
+
+/// Printed by the program after replaying the session, so that only what the latest input writes is shown.
+const OUTPUT_MARKER: &str = "__rust_script_repl_output__";
+
+const HELP: &str = "\
+Enter a statement, an expression or an item such as a `use` declaration or a function.  \
+Statements and items are kept for the rest of the session, and the value of an expression is printed.
+
+Commands:
+    :dep SPEC    Add a dependency, in the same form as for `--dep`
+    :type EXPR   Show the type of an expression, which is evaluated
+    :reset       Forget the statements, items and dependencies added so far
+    :help        Show this help
+    :quit        Leave the session, as does Ctrl-D";
+
+/**
+What has been entered in a session so far.
+*/
+#[derive(Clone, Debug)]
+struct Session {
+    /// Items, such as `use` declarations and functions, which go at the top of the program.
+    items: Vec<String>,
+
+    /// Statements, which are run again each time to get to the latest input.
+    statements: Vec<String>,
+
+    /// Dependencies given on the command line or with `:dep`, sorted by name.
+    deps: Vec<(String, String)>,
+}
+
+impl Session {
+    fn new(deps: Vec<(String, String)>) -> Self {
+        Session {
+            items: Vec::new(),
+            statements: Vec::new(),
+            deps,
+        }
+    }
+}
+
+/**
+Runs an interactive session until the user leaves it.
+*/
+pub fn run(
+    args: &Args,
+    deps: Vec<(String, String)>,
+    wildcard_policy: WildcardPolicy,
+) -> MainResult<i32> {
+    let base_path: PathBuf = match &args.base_path {
+        Some(base_path) => base_path.into(),
+        None => std::env::current_dir()?,
+    };
+    let repl = Repl {
+        args,
+        wildcard_policy,
+        base_path,
+        session_id: std::process::id(),
+        built: RefCell::new(None),
+    };
+
+    let history_path = platform::repl_history_path();
+    let mut editor = DefaultEditor::new()?;
+    if let Err(e) = editor.load_history(&history_path) {
+        // There is no history until the first session ends.
+        info!("could not load history from {:?}: {}", history_path, e);
+    }
+
+    println!("Enter Rust statements or expressions, or :help for help.");
+    let result = read_eval_print(&repl, &mut editor, deps);
+    repl.clean_up();
+    result?;
+
+    if let Some(dir) = history_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    editor.save_history(&history_path)?;
+    Ok(0)
+}
+
+/**
+Reads and runs inputs until the user leaves the session.
+*/
+fn read_eval_print(
+    repl: &Repl,
+    editor: &mut DefaultEditor,
+    deps: Vec<(String, String)>,
+) -> MainResult<()> {
+    let mut session = Session::new(deps.clone());
+    let mut entry = String::new();
+    loop {
+        let prompt = if entry.is_empty() { ">> " } else { ".. " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                entry.push_str(&line);
+                entry.push('\n');
+            }
+            Err(ReadlineError::Interrupted) => {
+                entry.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        }
+        if is_incomplete(&entry) {
+            continue;
+        }
+
+        let input = std::mem::take(&mut entry);
+        let input = input.trim();
+        if input.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(input)?;
+
+        if let Some(command) = input.strip_prefix(':') {
+            let (command, arg) = command
+                .split_once(char::is_whitespace)
+                .map_or((command, ""), |(command, arg)| (command, arg.trim()));
+            match (command, arg) {
+                ("dep", spec) if !spec.is_empty() => {
+                    // Relative paths are relative to the base path, as for the session's code.
+                    let base_path = std::env::current_dir()?.join(&repl.base_path);
+                    let dep = match DepSpec::parse(spec, &base_path) {
+                        Ok(dep) => dep,
+                        Err(e) => {
+                            eprintln!("error: {}", e);
+                            continue;
+                        }
+                    };
+                    let mut next = session.clone();
+                    next.deps.retain(|(name, _)| *name != dep.name);
+                    next.deps.push((dep.name.clone(), dep.to_manifest_value()));
+                    next.deps.sort();
+                    // Build the dependency right away, rather than with whatever comes next.
+                    if repl.evaluate(&next, "")? {
+                        session = next;
+                    }
+                }
+                ("type", expr) if !expr.is_empty() => {
+                    repl.evaluate(&session, &type_of(expr))?;
+                }
+                ("reset", "") => session = Session::new(deps.clone()),
+                ("help", "") => println!("{}", HELP),
+                ("quit", "") => break,
+                _ => eprintln!(
+                    "error: unknown command `:{}`; enter :help for help",
+                    command
+                ),
+            }
+        } else if is_item(input) {
+            let mut next = session.clone();
+            next.items.push(input.into());
+            if repl.evaluate(&next, "")? {
+                session = next;
+            }
+        } else if repl.evaluate(&session, input)? && input.ends_with(';') {
+            session.statements.push(input.into());
+        }
+    }
+    Ok(())
+}
+
+/**
+What stays the same for each input of a session.
+*/
+struct Repl<'a> {
+    args: &'a Args,
+    wildcard_policy: WildcardPolicy,
+    base_path: PathBuf,
+    /// Keeps sessions which are run at the same time from building in the same package.
+    session_id: u32,
+    /// The package and binary built for the session, if they are in the cache.
+    built: RefCell<Option<(PathBuf, PathBuf)>>,
+}
+
+impl Repl<'_> {
+    /**
+    Builds and runs the session followed by `input`, and shows what the input writes.
+
+    Returns whether the program could be built and ran successfully, so that the input can be kept.
+    */
+    fn evaluate(&self, session: &Session, input: &str) -> MainResult<bool> {
+        let mut script = String::new();
+        for statement in &session.statements {
+            script.push_str(statement);
+            script.push('\n');
+        }
+        script.push_str(&format!(
+            "::std::println!(\"{0}\");\n::std::eprintln!(\"{0}\");\n",
+            OUTPUT_MARKER
+        ));
+        script.push_str(input);
+        script.push('\n');
+
+        // Anything entered earlier that isn't used again is of no interest.
+        let mut prelude = vec!["#![allow(unused)]".to_string()];
+        prelude.extend(session.items.iter().cloned());
+
+        let input = Input::Repl(script, self.session_id, self.base_path.clone());
+        let mut action = match crate::decide_action_for(
+            &input,
+            session.deps.clone(),
+            prelude,
+            self.args,
+            self.wildcard_policy,
+        ) {
+            Ok(action) => action,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return Ok(false);
+            }
+        };
+        if action.using_cache {
+            *self.built.borrow_mut() = Some((action.pkg_path.clone(), action.built_binary_path()));
+        }
+        // Inputs come quickly enough that the binary's timestamp can't tell whether it is up to date, but cargo can.
+        action.force_compile = true;
+        crate::generate_package(&action)?;
+
+        let output = match action.command_to_execute() {
+            Ok(mut cmd) => cmd.output()?,
+            Err(e) => {
+                // Cargo has already explained why.
+                eprintln!("error: {}", e);
+                return Ok(false);
+            }
+        };
+        print!("{}", after_marker(&String::from_utf8_lossy(&output.stdout)));
+        eprint!("{}", after_marker(&String::from_utf8_lossy(&output.stderr)));
+        Ok(output.status.success())
+    }
+
+    /**
+    Removes the package and binary built for the session, which no other session can use.
+    */
+    fn clean_up(&self) {
+        if let Some((pkg_path, binary_path)) = self.built.take() {
+            info!("removing {:?} and {:?}", pkg_path, binary_path);
+            if let Err(e) = fs::remove_dir_all(&pkg_path) {
+                info!("could not remove {:?}: {}", pkg_path, e);
+            }
+            // Along with the binary goes the file cargo lists its dependencies in.
+            for path in [binary_path.with_extension("d"), binary_path] {
+                if let Err(e) = fs::remove_file(&path) {
+                    info!("could not remove {:?}: {}", path, e);
+                }
+            }
+        }
+    }
+}
+
+/**
+Returns the output following the marker, or all of it if replaying the session failed before getting there.
+*/
+fn after_marker(output: &str) -> &str {
+    output
+        .split_once(&format!("{}\n", OUTPUT_MARKER))
+        .map_or(output, |(_, after)| after)
+}
+
+/**
+Wraps an expression so that it prints its type instead of its value.
+*/
+pub fn type_of(expr: &str) -> String {
+    format!(
+        "{{ fn __rust_script_type_name<T: ?Sized>(_: &T) -> &'static str {{ ::std::any::type_name::<T>() }} ::std::println!(\"{{}}\", __rust_script_type_name(&({}))); }}",
+        expr
+    )
+}
+
+/**
+Returns `true` if the input is an item, which goes at the top of the program rather than in `main`.
+*/
+fn is_item(input: &str) -> bool {
+    let re_item: Regex = Regex::new(
+        r"^(#\[|(pub(\([^)]*\))?\s+)?((async\s+|const\s+|unsafe\s+)*fn|use|struct|enum|union|trait|impl|mod|type|const|static|extern|macro_rules!)\b)",
+    )
+    .unwrap();
+    re_item.is_match(input)
+}
+
+/**
+Returns `true` if the input has brackets or a string still open, so that more lines are needed.
+*/
+fn is_incomplete(input: &str) -> bool {
+    let chars: Vec<char> = input.chars().collect();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return true;
+                }
+            }
+            // A character literal, rather than a lifetime.
+            '\'' if chars.get(i + 2) == Some(&'\'') => i += 2,
+            '\'' if chars.get(i + 1) == Some(&'\\') => {
+                while i + 1 < chars.len() && chars[i + 1] != '\'' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            '/' if chars.get(i + 1) == Some(&'/') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    depth > 0
+}
+
+#[test]
+fn test_is_item() {
+    assert!(is_item("use std::collections::HashMap;"));
+    assert!(is_item("fn double(x: i32) -> i32 { x * 2 }"));
+    assert!(is_item("pub(crate) async fn f() {}"));
+    assert!(is_item("#[derive(Debug)] struct Point { x: i32 }"));
+    assert!(is_item("impl Point {}"));
+    assert!(!is_item("let used = 1;"));
+    assert!(!is_item("user_count + 1"));
+    assert!(!is_item("println!(\"fn f() {{}}\");"));
+}
+
+#[test]
+fn test_is_incomplete() {
+    assert!(!is_incomplete("let x = 1;\n"));
+    assert!(is_incomplete("fn f() {\n"));
+    assert!(!is_incomplete("fn f() {\n    1\n}\n"));
+    assert!(!is_incomplete("let c = '{';\n"));
+    assert!(!is_incomplete("let c = '\\'';\n"));
+    assert!(!is_incomplete("fn f<'a>(s: &'a str) -> &'a str { s }\n"));
+    assert!(!is_incomplete("let s = \"{\";\n"));
+    assert!(is_incomplete("let s = \"first line\n"));
+    assert!(!is_incomplete("let v = vec![1]; // [\n"));
+}
+
+#[test]
+fn test_after_marker() {
+    assert_eq!(
+        after_marker("replayed\n__rust_script_repl_output__\nnew\n"),
+        "new\n"
+    );
+    assert_eq!(after_marker("panicked early\n"), "panicked early\n");
+}
@@ -0,0 +1,7 @@
+---cargo
+[dependencies]
+path-dep = { path = "path-dep", features = ["shout"] }
+---
+pub fn greet(name: &str) -> String {
+    format!("{}, {}", path_dep::greeting(), name)
+}
@@ -0,0 +1,9 @@
+// mod not_a_module;
+mod tool {
+    pub mod parts;
+}
+
+fn main() {
+    println!("--output--");
+    println!("{}", tool::parts::greeting());
+}
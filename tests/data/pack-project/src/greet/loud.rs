@@ -0,0 +1,3 @@
+pub fn greeting() -> String {
+    format!("{}, packed", path_dep::greeting())
+}
@@ -0,0 +1,7 @@
+//! A project to be packed into a script.
+mod greet;
+
+fn main() {
+    println!("--output--");
+    println!("{}", greet::loud::greeting());
+}
@@ -0,0 +1,7 @@
+pub fn greeting() -> &'static str {
+    if cfg!(feature = "shout") {
+        "HELLO"
+    } else {
+        "hello"
+    }
+}
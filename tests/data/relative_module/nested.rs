@@ -0,0 +1 @@
+pub const GREETING: &str = include_str!("../file-to-be-included.txt");
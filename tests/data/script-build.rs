@@ -0,0 +1,15 @@
+#!/usr/bin/env rust-script
+//! ```cargo
+//! [build-dependencies]
+//! path-dep = { path = "path-dep", features = ["shout"] }
+//! ```
+//!
+//! ```rust,build
+//! fn main() {
+//!     println!("cargo:rustc-env=BUILD_GREETING={}, build script", path_dep::greeting());
+//! }
+//! ```
+fn main() {
+    println!("--output--");
+    println!("{}", env!("BUILD_GREETING"));
+}
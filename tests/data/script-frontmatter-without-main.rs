@@ -0,0 +1,6 @@
+---
+[package]
+edition = "2021"
+---
+println!("--output--");
+println!("Hello, frontmatter!");
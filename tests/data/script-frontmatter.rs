@@ -0,0 +1,9 @@
+#!/usr/bin/env rust-script
+---cargo
+[package]
+edition = "2021"
+---
+fn main() {
+    println!("--output--");
+    println!("Hello, frontmatter!");
+}
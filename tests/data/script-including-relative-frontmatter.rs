@@ -0,0 +1,12 @@
+#!/usr/bin/env rust-script
+---cargo
+[package]
+edition = "2021"
+---
+mod relative_module;
+
+fn main() {
+    println!("--output--");
+    println!("{}", relative_module::nested::GREETING);
+    assert_eq!(include_bytes!("file-to-be-included.txt"), relative_module::nested::GREETING.as_bytes());
+}
@@ -0,0 +1,9 @@
+mod relative_module;
+#[path = "script-module.rs"]
+mod script_module;
+
+println!("--output--");
+let s = include_str!("file-to-be-included.txt");
+assert_eq!(script_module::A_VALUE, 1);
+assert_eq!(relative_module::nested::GREETING, s);
+println!("{}", s);
@@ -0,0 +1,9 @@
+#!/usr/bin/env rust-script
+//! ```cargo
+//! [dependencies]
+//! common = { script = "library-common.rs" }
+//! ```
+fn main() {
+    println!("--output--");
+    println!("{}", common::greet("library"));
+}
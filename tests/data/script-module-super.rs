@@ -0,0 +1,17 @@
+#!/usr/bin/env rust-script
+---cargo
+[package]
+edition = "2021"
+---
+mod super_module;
+    pub mod super_parent;
+
+fn greeting() -> &'static str {
+    "hello from the crate root"
+}
+
+fn main() {
+    println!("--output--");
+    println!("{}", super_module::greeting());
+    println!("{}", super_parent::child::greeting());
+}
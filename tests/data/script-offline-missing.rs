@@ -0,0 +1,5 @@
+// cargo-deps: rust-script-test-crate-that-does-not-exist="1"
+fn main() {
+    println!("--output--");
+    println!("unreachable");
+}
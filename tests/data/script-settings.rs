@@ -0,0 +1,21 @@
+//! ```cargo
+//! [features]
+//! loud = []
+//!
+//! [package.metadata.rustscript]
+//! debug = true
+//! features = ["loud"]
+//! args = ["--from-manifest"]
+//! env = { _RUST_SCRIPT_TEST_SETTING = "from manifest" }
+//! ```
+fn main() {
+    println!("--output--");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    println!("args = {}", args.join(" "));
+    println!(
+        "env = {}",
+        std::env::var("_RUST_SCRIPT_TEST_SETTING").unwrap()
+    );
+    println!("loud = {}", cfg!(feature = "loud"));
+    println!("debug = {}", cfg!(debug_assertions));
+}
@@ -0,0 +1,5 @@
+// cargo-deps: path-dep(shout, path="path-dep")
+fn main() {
+    println!("--output--");
+    println!("{}", path_dep::greeting());
+}
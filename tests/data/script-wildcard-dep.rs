@@ -0,0 +1,5 @@
+// cargo-deps: rust-script-test-wildcard-dep
+fn main() {
+    println!("--output--");
+    println!("unreachable");
+}
@@ -0,0 +1,3 @@
+pub fn greeting() -> String {
+    format!("module: {}", super::greeting())
+}
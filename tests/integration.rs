@@ -14,6 +14,7 @@ mod util;
 
 mod tests {
     mod expr;
+    mod loops;
     mod others;
     mod script;
 }
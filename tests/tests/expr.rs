@@ -47,6 +47,52 @@ fn test_expr_dep() {
     .unwrap();
 }
 
+#[test]
+fn test_expr_path_dep() {
+    let out = rust_script!(
+        "-d",
+        "path-dep[shout]:path=tests/data/path-dep",
+        "-e",
+        with_output_marker!("path_dep::greeting()")
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("\"HELLO\"") => ()
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_expr_invalid_dep() {
+    let out = rust_script!("-d", "serde@1[derive", "-e", "1").unwrap();
+    assert!(!out.success());
+    assert!(out
+        .stderr
+        .contains("invalid dependency `serde@1[derive`: missing `]` after features"));
+}
+
+#[test]
+fn test_expr_wildcard_deps() {
+    let out = rust_script!(
+        "--wildcard-deps",
+        "deny",
+        "-d",
+        "serde",
+        "-d",
+        "mylib:git=https://example.com/mylib.git",
+        "-e",
+        "1"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out
+        .stderr
+        .contains("dependency `serde` allows any version (from the command line)"));
+    assert!(out.stderr.contains(
+        "dependency `mylib` is a git dependency without a pinned `rev` (from the command line)"
+    ));
+}
+
 #[test]
 fn test_expr_panic() {
     let out = rust_script!("-e", with_output_marker!("panic!()")).unwrap();
@@ -62,3 +108,122 @@ fn test_expr_qmark() {
     )
     .unwrap();
 }
+
+#[test]
+fn test_expr_format() {
+    let out = rust_script!("--format", "display", "-e", "\"no quotes\"").unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "no quotes\n");
+
+    let out = rust_script!("--format", "pretty", "-e", "(1, \"a\")").unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "(\n    1,\n    \"a\",\n)\n");
+
+    let out = rust_script!("--format", "raw", "-e", "\"no line ending\"").unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "no line ending");
+
+    // `()` is still not printed, even though it isn't `Display`.
+    let out = rust_script!("--format", "display", "-e", "()").unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "");
+}
+
+#[cfg_attr(not(feature = "online_tests"), ignore)]
+#[test]
+fn test_expr_format_json() {
+    let out = rust_script!(
+        "--format",
+        "json",
+        "-e",
+        "std::collections::BTreeMap::from([(\"a\", vec![1, 2])])"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "{\"a\":[1,2]}\n");
+}
+
+#[test]
+fn test_expr_type() {
+    let out = rust_script!("--type", "-e", "[1u8].iter().max()").unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "core::option::Option<&u8>\n");
+
+    let out = rust_script!("--type", "-l", "|line| line").unwrap();
+    assert!(!out.success());
+}
+
+#[test]
+fn test_repl() {
+    let session = r#"let x = 20;
+println!("replayed, so only shown once");
+fn double(n: i32) -> i32 {
+    n * 2
+}
+let broken = not_defined;
+:dep path-dep:path=tests/data/path-dep
+println!("--output--")
+double(x) + 2
+:type x
+path_dep::greeting()
+:reset
+x
+"#;
+    let out = rust_script!(
+        #[stdin(session)]
+        "--repl"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(
+        out.stdout.matches("replayed, so only shown once").count(),
+        1
+    );
+    assert_eq!(out.stdout_output(), "\n42\ni32\n\"hello\"\n");
+    assert!(out.stderr.contains("cannot find value `not_defined`"));
+    // The session was reset, so `x` is gone.
+    assert!(out.stderr.contains("cannot find value `x`"));
+}
+
+#[test]
+fn test_repl_base_path() {
+    let session = ":dep path-dep:path=path-dep\nprintln!(\"--output--\")\npath_dep::greeting()\n";
+    let out = rust_script!(
+        #[stdin(session)]
+        "--base-path",
+        "tests/data",
+        "--repl"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout_output(), "\n\"hello\"\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_repl_cleans_up() {
+    let cache_home = tempfile::tempdir().unwrap();
+    let out = rust_script!(
+        #[env(XDG_CACHE_HOME = cache_home.path())]
+        #[stdin("println!(\"--output--\")\n1 + 1\n")]
+        "--repl"
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output(), "\n2\n");
+
+    // The session's package and binary are of no use to any other session.
+    let cache = cache_home.path().join("rust-script");
+    assert_eq!(
+        std::fs::read_dir(cache.join("projects")).unwrap().count(),
+        0
+    );
+    for profile in std::fs::read_dir(cache.join("binaries")).unwrap() {
+        let profile = profile.unwrap().path();
+        if profile.is_dir() {
+            for file in std::fs::read_dir(&profile).unwrap() {
+                let name = file.unwrap().file_name();
+                assert!(!name.to_string_lossy().starts_with("repl_"), "{:?}", name);
+            }
+        }
+    }
+}
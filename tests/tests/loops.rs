@@ -0,0 +1,370 @@
+#[test]
+fn test_loop_line() {
+    let out = rust_script!(
+        #[stdin("first\nsecond\n")]
+        "-l",
+        "|line| line.len()"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "6\n7\n");
+}
+
+#[test]
+fn test_loop_count() {
+    let out = rust_script!(
+        #[stdin("first\nsecond\n")]
+        "--count",
+        "-l",
+        "|line, n| (n, line.trim().to_string())"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "(1, \"first\")\n(2, \"second\")\n");
+}
+
+#[test]
+fn test_loop_split() {
+    let out = rust_script!(
+        #[stdin("name,count\napples,3\nempty\n")]
+        "-F",
+        ",",
+        "-l",
+        "|fields| fields.get(1).map(|f| f.to_string())"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "\"count\"\n\"3\"\n");
+
+    // A single space splits at runs of whitespace, as in awk.
+    let out = rust_script!(
+        #[stdin("  apples   3\n")]
+        "--split",
+        " ",
+        "--count",
+        "-l",
+        "|fields, n| format!(\"{}:{}\", n, fields.join(\"|\"))"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "\"1:apples|3\"\n");
+}
+
+#[test]
+fn test_loop_regex() {
+    let out = rust_script!(
+        #[stdin("id=7\nno id here\nid=42\n")]
+        "--regex",
+        r"^id=(?P<id>\d+)$",
+        "-l",
+        "|captures| captures[\"id\"].parse::<u32>().unwrap() * 2"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "14\n84\n");
+
+    let out = rust_script!("--regex", "(", "-l", "|c| 1").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("invalid `--regex` pattern"));
+}
+
+#[test]
+fn test_loop_parse() {
+    let out = rust_script!(
+        #[stdin("3\n4\n")]
+        "--parse",
+        "i64",
+        "-l",
+        "|n| n * n"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "9\n16\n");
+
+    let out = rust_script!(
+        #[stdin("3\nthree\n")]
+        "--parse",
+        "i64",
+        "-l",
+        "|n| n * n"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out
+        .stderr
+        .contains("could not parse line 2 (\"three\"): invalid digit found in string"));
+}
+
+#[test]
+fn test_loop_begin_end() {
+    let out = rust_script!(
+        #[stdin("apples,3\npears,4\n")]
+        "-F",
+        ",",
+        "--begin",
+        "0u64",
+        "--end",
+        "format!(\"{} in {} lines\", state, count)",
+        "-l",
+        "|total, fields| *total += fields[1].parse::<u64>().unwrap()"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "\"7 in 2 lines\"\n");
+
+    let out = rust_script!(
+        #[stdin("x\ny\nx\n")]
+        "--begin",
+        "std::collections::BTreeMap::<String, usize>::new()",
+        "--end",
+        "state",
+        "--count",
+        "-l",
+        "|seen, line, n| { *seen.entry(line.trim().to_string()).or_default() += 1; n }"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "1\n2\n3\n{\"x\": 2, \"y\": 1}\n");
+
+    // Without `--begin`, there is no state for the closure.
+    let out = rust_script!(
+        #[stdin("x\ny\n")]
+        "--end",
+        "count",
+        "-l",
+        "|line| ()"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "2\n");
+}
+
+#[test]
+fn test_loop_invalid_utf8() {
+    // Stops with an error, rather than as if the input had ended.
+    let out = rust_script!(
+        #[stdin(&b"ok\n\xffno\nlater\n"[..])]
+        "-l",
+        "|line| line.len()"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert_eq!(out.stdout, "3\n");
+    assert!(out
+        .stderr
+        .contains("error: line 2 is not valid UTF-8 (invalid utf-8 sequence of 1 bytes from index 0); use `--lossy` or `--bytes` to read it anyway"));
+
+    let out = rust_script!(
+        #[stdin(&b"ok\n\xffno\n"[..])]
+        "--lossy",
+        "-l",
+        "|line| line.trim().to_string()"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "\"ok\"\n\"\u{fffd}no\"\n");
+
+    let out = rust_script!(
+        #[stdin(&b"ok\n\xffno\n"[..])]
+        "--bytes",
+        "-l",
+        "|line| line.to_vec()"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "[111, 107, 10]\n[255, 110, 111, 10]\n");
+}
+
+#[test]
+fn test_loop_null() {
+    let out = rust_script!(
+        #[stdin("first file\0second\0")]
+        "-0",
+        "--count",
+        "-l",
+        "|path, n| format!(\"{}: {}\", n, path)"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "\"1: first file\"\n\"2: second\"\n");
+
+    let out = rust_script!(
+        #[stdin("a,b\0c\0")]
+        "--null",
+        "-F",
+        ",",
+        "-l",
+        "|fields| fields.len()"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "2\n1\n");
+}
+
+#[test]
+fn test_loop_format() {
+    // Lines keep their line ending, so `raw` passes them on as they are, like `sed`.
+    let out = rust_script!(
+        #[stdin("foo bar\nbaz foo\n")]
+        "--format",
+        "raw",
+        "-l",
+        "|line| line.replace(\"foo\", \"qux\")"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "qux bar\nbaz qux\n");
+
+    // The closure returns `()`, and there is no `--end`, neither of which are `Display`.
+    let out = rust_script!(
+        #[stdin("a\nb\n")]
+        "--format",
+        "display",
+        "-l",
+        "|line| ()"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "");
+
+    let out = rust_script!(
+        #[stdin("apples,3\npears,4\n")]
+        "--format",
+        "display",
+        "-F",
+        ",",
+        "--begin",
+        "0u64",
+        "--end",
+        "format!(\"{} in total\", state)",
+        "-l",
+        "|total, fields| *total += fields[1].parse::<u64>().unwrap()"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "7 in total\n");
+}
+
+#[test]
+fn test_loop_filter_map() {
+    // A `bool` keeps or drops the line as it was read, like grep.
+    let out = rust_script!(
+        #[stdin("apple\nbanana\ncherry")]
+        "-l",
+        "|line| line.contains('a')"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "apple\nbanana\n");
+
+    let out = rust_script!(
+        #[stdin("1\ntwo\n3\n")]
+        "--parse",
+        "String",
+        "-l",
+        "|line| line.parse::<i32>().ok()"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "1\n3\n");
+
+    // Errors are reported as they come, and the loop carries on, but fails in the end.
+    let out = rust_script!(
+        #[stdin("1\ntwo\n3\n")]
+        "--count",
+        "-l",
+        "|line, n| line.trim().parse::<i32>().map(|v| v * n as i32)"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert_eq!(out.stdout, "1\n9\n");
+    assert!(out
+        .stderr
+        .contains("error: line 2: invalid digit found in string"));
+
+    let out = rust_script!(
+        #[stdin("a b\nc\n")]
+        "--format",
+        "display",
+        "-l",
+        "|line| line.split_whitespace().map(str::to_uppercase).collect::<Vec<_>>().into_iter()"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "A\nB\nC\n");
+}
+
+#[test]
+fn test_loop_parallel() {
+    // Enough lines for several batches, whose results still come out in order.
+    let input: String = (1..=3000).map(|n| format!("{}\n", n)).collect();
+    let expected: String = (1..=3000).map(|n| format!("{}\n", n * 2)).collect();
+    let out = rust_script!(
+        #[stdin(input.clone())]
+        "--parallel=3",
+        "--parse",
+        "u64",
+        "-l",
+        "|n| n * 2"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, expected);
+
+    let out = rust_script!(
+        #[stdin(input)]
+        "--parallel",
+        "--count",
+        "--end",
+        "count",
+        "-l",
+        "|line, n| n % 1000 == 0"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "1000\n2000\n3000\n3000\n");
+
+    // An error still comes after the output of the lines before it.
+    let out = rust_script!(
+        #[stdin("1\nx\n3\n")]
+        "--parallel=2",
+        "--parse",
+        "i32",
+        "-l",
+        "|n| n"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert_eq!(out.stdout, "1\n");
+    assert!(out
+        .stderr
+        .contains("error: could not parse line 2 (\"x\"): invalid digit found in string"));
+
+    let out = rust_script!(
+        #[stdin("a\n")]
+        "--parallel",
+        "-l",
+        "let mut n = 0; move |line| { n += 1; n }"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("`Fn` closure"));
+}
+
+#[test]
+fn test_loop_parallel_panic() {
+    let out = rust_script!(
+        #[stdin("1\n2\n3\n")]
+        "--parallel=2",
+        "--parse",
+        "i32",
+        "-l",
+        "|n| if n == 2 { panic!(\"no twos\") } else { n }"
+    )
+    .unwrap();
+    assert_eq!(out.status.code(), Some(101));
+    assert_eq!(out.stdout, "1\n");
+    assert!(out.stderr.contains("no twos"));
+    assert!(out.stderr.contains("error: the closure panicked on line 2"));
+    assert!(!out.stderr.contains("unwrap()"));
+}
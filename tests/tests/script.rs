@@ -38,6 +38,44 @@ fn test_script_full_line_without_main() {
     .unwrap()
 }
 
+#[test]
+fn test_script_frontmatter() {
+    let out = rust_script!("tests/data/script-frontmatter.rs").unwrap();
+    scan!(out.stdout_output();
+        ("Hello, frontmatter!") => ()
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_script_frontmatter_without_main() {
+    let out = rust_script!("tests/data/script-frontmatter-without-main.rs").unwrap();
+    scan!(out.stdout_output();
+        ("Hello, frontmatter!") => ()
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_script_settings() {
+    let out = rust_script!("tests/data/script-settings.rs").unwrap();
+    scan!(out.stdout_output();
+        ("args = --from-manifest", "env = from manifest", "loud = true", "debug = true") => ()
+    )
+    .unwrap();
+
+    let out = rust_script!(
+        #[env(_RUST_SCRIPT_TEST_SETTING = "from environment")]
+        "tests/data/script-settings.rs",
+        "--from-command-line"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("args = --from-command-line", "env = from environment", "loud = true", "debug = true") => ()
+    )
+    .unwrap()
+}
+
 #[test]
 fn test_script_main_with_space() {
     let out = rust_script!("tests/data/script-main-with-spaces.rs").unwrap();
@@ -65,6 +103,545 @@ fn test_script_no_deps() {
     .unwrap()
 }
 
+#[test]
+fn test_script_short_path_dep() {
+    let out = rust_script!("tests/data/script-short-path-dep.rs").unwrap();
+    scan!(out.stdout_output();
+        ("HELLO") => ()
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_script_lockfile() {
+    let dir = tempfile::tempdir().unwrap();
+    let script_path = dir.path().join("script-lockfile.rs");
+    std::fs::write(
+        &script_path,
+        "---\n[package.metadata.rustscript]\nlockfile = true\n---\nfn main() {\n    println!(\"--output--\");\n    println!(\"locked\");\n}\n",
+    )
+    .unwrap();
+    let lockfile_path = dir.path().join("script-lockfile.rs.lock");
+
+    let out = rust_script!(&script_path).unwrap();
+    scan!(out.stdout_output();
+        ("locked") => ()
+    )
+    .unwrap();
+    let lockfile = std::fs::read_to_string(&lockfile_path).unwrap();
+    assert!(lockfile.contains("[[package]]"), "{}", lockfile);
+
+    let out = rust_script!("--locked", &script_path).unwrap();
+    scan!(out.stdout_output();
+        ("locked") => ()
+    )
+    .unwrap();
+
+    std::fs::remove_file(&lockfile_path).unwrap();
+    let out = rust_script!("--locked", &script_path).unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("`--locked` requires a lockfile"));
+}
+
+#[test]
+fn test_script_lockfile_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let script_path = dir.path().join("script-lockfile-test.rs");
+    std::fs::write(
+        &script_path,
+        "---\n[package.metadata.rustscript]\nlockfile = true\n---\nfn main() {}\n\n#[test]\nfn locked() {}\n",
+    )
+    .unwrap();
+    let lockfile_path = dir.path().join("script-lockfile-test.rs.lock");
+
+    let out = rust_script!("--test", &script_path).unwrap();
+    assert!(out.success());
+    assert!(out.stdout.contains("running 1 test"));
+    let lockfile = std::fs::read_to_string(&lockfile_path).unwrap();
+    assert!(lockfile.contains("[[package]]"), "{}", lockfile);
+}
+
+#[test]
+fn test_script_offline() {
+    let out = rust_script!("--offline", "tests/data/script-short-path-dep.rs").unwrap();
+    scan!(out.stdout_output();
+        ("HELLO") => ()
+    )
+    .unwrap();
+
+    let out = rust_script!("--offline", "tests/data/script-offline-missing.rs").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("rust-script --fetch"));
+
+    let out = rust_script!(
+        "--offline",
+        "--test",
+        "tests/data/script-offline-missing.rs"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("rust-script --fetch"));
+
+    // A script which doesn't compile has nothing to do with the crates in the cache.
+    let out = rust_script!("--offline", "-e", "not_defined").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("cannot find value `not_defined`"));
+    assert!(!out.stderr.contains("rust-script --fetch"));
+}
+
+#[test]
+fn test_script_fetch() {
+    let out = rust_script!("--fetch", "tests/data/script-short-path-dep.rs").unwrap();
+    assert!(out.success());
+    assert!(!out.stdout.contains("HELLO"));
+}
+
+#[test]
+fn test_script_eject() {
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().join("project");
+
+    let out = rust_script!("--eject", &project, "tests/data/script-short-path-dep.rs").unwrap();
+    assert!(out.success());
+
+    let main = std::fs::read_to_string(project.join("src/main.rs")).unwrap();
+    assert!(!main.contains("cargo-deps"), "{}", main);
+    let manifest = std::fs::read_to_string(project.join("Cargo.toml")).unwrap();
+    assert!(
+        manifest.contains(r#"name = "script-short-path-dep""#),
+        "{}",
+        manifest
+    );
+    assert!(!manifest.contains("authors"), "{}", manifest);
+    assert!(!manifest.contains("path = \"/"), "{}", manifest);
+
+    let out = std::process::Command::new("cargo")
+        .args(["run", "--quiet", "--offline"])
+        .current_dir(&project)
+        .env_remove("CARGO_TARGET_DIR")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "--output--\nHELLO\n");
+
+    let out = rust_script!("--eject", &project, "tests/data/script-short-path-dep.rs").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("the directory is not empty"));
+}
+
+#[test]
+fn test_script_build_script() {
+    let out = rust_script!("tests/data/script-build.rs").unwrap();
+    scan!(out.stdout_output();
+        ("HELLO, build script") => ()
+    )
+    .unwrap();
+
+    // Changing only the build script has to trigger a rebuild.
+    let dir = tempfile::tempdir().unwrap();
+    let script_path = dir.path().join("script.rs");
+    let script = |value: &str| {
+        format!(
+            "//! ```rust,build\n//! fn main() {{ println!(\"cargo:rustc-env=VALUE={}\"); }}\n//! ```\nprintln!(\"--output--\");\nprintln!(\"{{}}\", env!(\"VALUE\"));\n",
+            value
+        )
+    };
+    std::fs::write(&script_path, script("first")).unwrap();
+    let out = rust_script!(&script_path).unwrap();
+    scan!(out.stdout_output();
+        ("first") => ()
+    )
+    .unwrap();
+
+    std::fs::write(&script_path, script("second")).unwrap();
+    let out = rust_script!(&script_path).unwrap();
+    scan!(out.stdout_output();
+        ("second") => ()
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_script_markdown() {
+    let out = rust_script!("tests/data/script-markdown.md").unwrap();
+    scan!(out.stdout_output();
+        ("hello, Markdown") => ()
+    )
+    .unwrap();
+
+    let out = rust_script!("--block", "check", "tests/data/script-markdown.md").unwrap();
+    scan!(out.stdout_output();
+        ("checked hello") => ()
+    )
+    .unwrap();
+
+    let out = rust_script!("--block", "missing", "tests/data/script-markdown.md").unwrap();
+    assert!(!out.success());
+    assert!(out
+        .stderr
+        .contains("the Markdown script has no `rust` code block named `missing`"));
+}
+
+#[test]
+fn test_script_markdown_error_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let script_path = dir.path().join("broken.md");
+    std::fs::write(
+        &script_path,
+        "# Broken\n\nSome prose.\n\n```rust\nlet n: u32 = \"text\";\n```\n",
+    )
+    .unwrap();
+    let out = rust_script!(&script_path).unwrap();
+    assert!(!out.success());
+    // The error is on the sixth line of the Markdown.
+    assert!(out.stderr.contains("broken.rs:6:14"), "{}", out.stderr);
+}
+
+#[test]
+fn test_script_library() {
+    let out = rust_script!("tests/data/script-library.rs").unwrap();
+    scan!(out.stdout_output();
+        ("HELLO, library") => ()
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_script_library_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let script_path = dir.path().join("script.rs");
+    let helper_path = dir.path().join("helper.rs");
+    std::fs::write(
+        &script_path,
+        "// cargo-deps: helper(script=\"helper.rs\")\nprintln!(\"--output--\");\nprintln!(\"{}\", helper::VALUE);\n",
+    )
+    .unwrap();
+
+    std::fs::write(&helper_path, "pub const VALUE: &str = \"first\";\n").unwrap();
+    let out = rust_script!(&script_path).unwrap();
+    scan!(out.stdout_output();
+        ("first") => ()
+    )
+    .unwrap();
+
+    // Only the library changes, which still has to trigger a rebuild.
+    std::fs::write(&helper_path, "pub const VALUE: &str = \"second\";\n").unwrap();
+    let out = rust_script!(&script_path).unwrap();
+    scan!(out.stdout_output();
+        ("second") => ()
+    )
+    .unwrap();
+
+    std::fs::write(
+        &helper_path,
+        "// cargo-deps: script(script=\"script.rs\")\npub const VALUE: &str = \"third\";\n",
+    )
+    .unwrap();
+    let out = rust_script!(&script_path).unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("scripts depend on each other"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn test_script_stdin() {
+    let script = r#"#!/usr/bin/env rust-script
+---cargo
+[dependencies]
+path-dep = { path = "tests/data/path-dep", features = ["shout"] }
+---
+let mut line = String::new();
+std::io::stdin().read_line(&mut line).unwrap();
+println!("--output--");
+println!("{}, {} {}", path_dep::greeting(), line.trim(), std::env::args().nth(1).unwrap());
+__END__
+from stdin
+"#;
+    let out = rust_script!(
+        #[stdin(script)]
+        "-",
+        "with args"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("HELLO, from stdin with args") => ()
+    )
+    .unwrap();
+
+    let out = rust_script!(
+        #[stdin("println!(\"--output--\"); println!(\"no main\");")]
+        "--stdin"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("no main") => ()
+    )
+    .unwrap();
+}
+
+#[cfg(feature = "remote")]
+#[test]
+fn test_script_url() {
+    use sha2::{Digest, Sha256};
+    use std::io::{Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let script = "println!(\"--output--\");\nprintln!(\"{}\", std::env::var(\"RUST_SCRIPT_URL\").unwrap());\n";
+    let sha256 = format!("{:x}", Sha256::digest(script));
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/served.rs", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let served = requests.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let _ = stream.read(&mut [0; 4096]);
+            served.fetch_add(1, Ordering::SeqCst);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                script.len(),
+                script
+            );
+        }
+    });
+
+    let out = rust_script!(&url).unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("must be pinned with `--sha256`"));
+
+    let out = rust_script!("--sha256", "0".repeat(64), &url).unwrap();
+    assert!(!out.success());
+    assert!(out
+        .stderr
+        .contains(&format!("has SHA-256 digest {}", sha256)));
+
+    let out = rust_script!("--sha256", &sha256, &url).unwrap();
+    scan!(out.stdout_output();
+        ("http://127.0.0.1:", let _: u16, "/served.rs") => ()
+    )
+    .unwrap();
+
+    // The verified copy is used from then on.
+    let fetched = requests.load(Ordering::SeqCst);
+    let out = rust_script!("--sha256", &sha256, &url).unwrap();
+    assert!(out.success());
+    assert_eq!(requests.load(Ordering::SeqCst), fetched);
+}
+
+#[test]
+fn test_script_file_url() {
+    use sha2::{Digest, Sha256};
+
+    let script = "println!(\"--output--\");\nprintln!(\"{}\", std::env::var(\"RUST_SCRIPT_URL\").unwrap());\n";
+    let sha256 = format!("{:x}", Sha256::digest(script));
+
+    let dir = tempfile::tempdir().unwrap();
+    let script_path = dir.path().join("local.rs");
+    std::fs::write(&script_path, script).unwrap();
+    let file_url = format!("file://{}", script_path.display()).replace('\\', "/");
+
+    let out = rust_script!(&file_url).unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("must be pinned with `--sha256`"));
+
+    let out = rust_script!("--sha256", &sha256, &file_url).unwrap();
+    scan!(out.stdout_output();
+        ("file://", .._) => ()
+    )
+    .unwrap();
+
+    // Only `file://` URLs can be read without the `remote` feature.
+    if cfg!(not(feature = "remote")) {
+        let out = rust_script!("--sha256", &sha256, "http://127.0.0.1:1/served.rs").unwrap();
+        assert!(!out.success());
+        assert!(out.stderr.contains("without the `remote` feature"));
+    }
+}
+
+#[test]
+fn test_script_pack() {
+    let dir = tempfile::tempdir().unwrap();
+    let script_path = dir.path().join("packed.rs");
+
+    let out = rust_script!("--pack", "tests/data/pack-project", &script_path).unwrap();
+    assert!(out.success());
+
+    let script = std::fs::read_to_string(&script_path).unwrap();
+    assert!(script.starts_with("#!/usr/bin/env rust-script\n//! ```cargo\n"));
+    assert!(script.contains("pub mod loud {"), "{}", script);
+    assert!(!script.contains("pub mod loud;"), "{}", script);
+
+    let out = rust_script!(&script_path).unwrap();
+    scan!(out.stdout_output();
+        ("HELLO, packed") => ()
+    )
+    .unwrap();
+
+    let out = rust_script!("--pack", "tests/data/pack-project", &script_path).unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("the file already exists"));
+}
+
+#[test]
+fn test_script_pack_bin_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let script_path = dir.path().join("tool.rs");
+
+    let out = rust_script!("--pack", "tests/data/pack-bin-project", &script_path).unwrap();
+    assert!(out.success());
+
+    // Modules are found next to the binary, including those of inline modules, but not in comments.
+    let script = std::fs::read_to_string(&script_path).unwrap();
+    assert!(script.contains("// mod not_a_module;\n"), "{}", script);
+    assert!(script.contains("pub mod parts {"), "{}", script);
+    assert!(!script.contains("[[bin]]"), "{}", script);
+
+    let out = rust_script!(&script_path).unwrap();
+    scan!(out.stdout_output();
+        ("from a custom bin path") => ()
+    )
+    .unwrap();
+
+    // The output path is the second value of `--pack`, and there is no script.
+    let out = rust_script!("--pack", "tests/data/pack-bin-project", "a.rs", "b.rs").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("cannot be used with"));
+}
+
+#[test]
+fn test_script_edit_dependencies() {
+    let dir = tempfile::tempdir().unwrap();
+    let script_path = dir.path().join("script.rs");
+    std::fs::write(
+        &script_path,
+        "// cargo-deps: time=\"0.1.25\"\nfn main() {\n    println!(\"--output--\");\n    println!(\"{}\", path_dep::greeting());\n}\n",
+    )
+    .unwrap();
+    let path_dep = std::env::current_dir().unwrap().join("tests/data/path-dep");
+
+    let out = rust_script!(
+        "add",
+        &script_path,
+        format!("path-dep[shout]:path={}", path_dep.display())
+    )
+    .unwrap();
+    assert!(out.success());
+    let out = rust_script!("remove", &script_path, "time").unwrap();
+    assert!(out.success());
+
+    let script = std::fs::read_to_string(&script_path).unwrap();
+    assert!(
+        script.starts_with("// cargo-deps: path-dep(shout, path=\""),
+        "{}",
+        script
+    );
+    assert!(!script.contains("time"), "{}", script);
+
+    let out = rust_script!(&script_path).unwrap();
+    scan!(out.stdout_output();
+        ("HELLO") => ()
+    )
+    .unwrap();
+
+    let out = rust_script!("remove", &script_path, "time").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("dependency `time` not found"));
+
+    let out = rust_script!("add", &script_path, "rust-script-test-not-in-the-index").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains(
+        "could not find `rust-script-test-not-in-the-index` in the local registry index"
+    ));
+    assert_eq!(std::fs::read_to_string(&script_path).unwrap(), script);
+}
+
+#[test]
+fn test_script_named_like_edit_command() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("add.rs"),
+        "fn main() {\n    println!(\"--output--\");\n    println!(\"{:?}\", std::env::args().skip(1).collect::<Vec<_>>());\n}\n",
+    )
+    .unwrap();
+    let target_dir = std::env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| String::from("target"));
+    let rust_script = std::env::current_dir()
+        .unwrap()
+        .join(target_dir)
+        .join("debug/rust-script");
+
+    let _cargo_lock = crate::util::CARGO_MUTEX.lock().unwrap();
+    let out = std::process::Command::new(rust_script)
+        .args(["add", "serde", "1"])
+        .current_dir(dir.path())
+        .env_remove("CARGO_TARGET_DIR")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(
+        stdout.ends_with("--output--\n[\"serde\", \"1\"]\n"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_script_wildcard_deps() {
+    let out = rust_script!(
+        "--wildcard-deps",
+        "deny",
+        "tests/data/script-wildcard-dep.rs"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains(
+        "dependency `rust-script-test-wildcard-dep` allows any version (from the short comment)"
+    ));
+
+    let out = rust_script!(
+        "--wildcard-deps",
+        "deny",
+        "tests/data/script-short-path-dep.rs"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("HELLO") => ()
+    )
+    .unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_script_wildcard_deps_config() {
+    let config_home = tempfile::tempdir().unwrap();
+    std::fs::create_dir(config_home.path().join("rust-script")).unwrap();
+    std::fs::write(
+        config_home.path().join("rust-script/config.toml"),
+        "wildcard-deps = \"deny\"\n",
+    )
+    .unwrap();
+
+    let out = rust_script!(
+        #[env(XDG_CONFIG_HOME = config_home.path())]
+        "tests/data/script-wildcard-dep.rs"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out
+        .stderr
+        .contains("allows any version (from the short comment)"));
+}
+
 #[cfg_attr(not(feature = "online_tests"), ignore)]
 #[test]
 fn test_script_short() {
@@ -162,6 +739,34 @@ fn test_script_including_relative() {
     .unwrap()
 }
 
+#[test]
+fn test_script_including_relative_without_main() {
+    let out = rust_script!("tests/data/script-including-relative-without-main.rs").unwrap();
+    scan!(out.stdout_output();
+        ("hello, including script") => ()
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_script_including_relative_frontmatter() {
+    let out = rust_script!("tests/data/script-including-relative-frontmatter.rs").unwrap();
+    scan!(out.stdout_output();
+        ("hello, including script") => ()
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_script_module_super() {
+    let out = rust_script!("tests/data/script-module-super.rs").unwrap();
+    assert!(out.success());
+    assert_eq!(
+        out.stdout_output(),
+        "\nmodule: hello from the crate root\nchild: hello from the crate root\n"
+    );
+}
+
 #[cfg_attr(not(feature = "online_tests"), ignore)]
 #[test]
 fn script_with_same_name_as_dependency() {
@@ -2,12 +2,14 @@ use std::sync::Mutex;
 
 macro_rules! rust_script {
     (
+        @stdin($stdin:expr)
         #[env($($env_k:ident=$env_v:expr),* $(,)*)]
         $($args:expr),* $(,)*
     ) => {
         {
             extern crate tempfile;
-            use std::process::Command;
+            use std::io::Write;
+            use std::process::{Command, Stdio};
 
             let cargo_lock = crate::util::CARGO_MUTEX.lock().expect("Could not acquire Cargo mutex");
 
@@ -25,8 +27,22 @@ macro_rules! rust_script {
 
                 cmd_str = format!("{:?}", cmd);
 
-                cmd.output()
-                    .map(crate::util::Output::from)
+                let stdin: Option<Vec<u8>> = $stdin;
+                match stdin {
+                    Some(stdin) => {
+                        let mut child = cmd
+                            .stdin(Stdio::piped())
+                            .stdout(Stdio::piped())
+                            .stderr(Stdio::piped())
+                            .spawn()
+                            .expect("Could not start rust-script");
+                        // The script may well stop reading before the end.
+                        let _ = child.stdin.take().unwrap().write_all(&stdin);
+                        child.wait_with_output()
+                    }
+                    None => cmd.output(),
+                }
+                .map(crate::util::Output::from)
             };
 
             if let Ok(out) = out.as_ref() {
@@ -47,6 +63,28 @@ macro_rules! rust_script {
         }
     };
 
+    (
+        #[env($($env_k:ident=$env_v:expr),* $(,)*)]
+        #[stdin($stdin:expr)]
+        $($args:expr),* $(,)*
+    ) => {
+        rust_script!(@stdin(Some(Vec::<u8>::from($stdin))) #[env($($env_k=$env_v),*)] $($args),*)
+    };
+
+    (
+        #[env($($env_k:ident=$env_v:expr),* $(,)*)]
+        $($args:expr),* $(,)*
+    ) => {
+        rust_script!(@stdin(None) #[env($($env_k=$env_v),*)] $($args),*)
+    };
+
+    (
+        #[stdin($stdin:expr)]
+        $($args:expr),* $(,)*
+    ) => {
+        rust_script!(@stdin(Some(Vec::<u8>::from($stdin))) #[env()] $($args),*)
+    };
+
     ($($args:expr),* $(,)*) => {
         rust_script!(#[env()] $($args),*)
     };